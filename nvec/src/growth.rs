@@ -0,0 +1,75 @@
+// How much an NVec grows by when a push or `reserve` runs out of room. `reserve_exact`,
+// `with_capacity` and `shrink_to_fit` ask for an exact size and don't go through this.
+// Doubling wastes up to half the capacity but reallocates the least, Exact wastes nothing
+// but reallocates on every push, the others sit in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GrowthPolicy {
+    // Double the capacity, starting at 4. What NVec always did, and the default.
+    #[default]
+    Doubling,
+    // Grow by half the capacity, starting at 4. Freed blocks can be reused by later growth,
+    // which never happens when doubling.
+    OneAndHalf,
+    // Add the same number of slots every time. Pushing in a loop is O(n^2) overall,
+    // but the waste is bounded by the increment. An increment of 0 behaves like Exact.
+    FixedIncrement(usize),
+    // Exactly as much room as asked for.
+    Exact,
+}
+
+impl GrowthPolicy {
+    // The capacity to grow to from `capacity` when `needed` slots are asked for,
+    // never less than `needed`.
+    pub fn next_capacity(&self, capacity: usize, needed: usize) -> usize {
+        let grown = match *self {
+            GrowthPolicy::Doubling => capacity.saturating_mul(2).max(4),
+            GrowthPolicy::OneAndHalf => capacity.saturating_add(capacity / 2).max(4),
+            GrowthPolicy::FixedIncrement(increment) => capacity.saturating_add(increment),
+            GrowthPolicy::Exact => needed,
+        };
+        grown.max(needed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GrowthPolicy;
+
+    // The capacities a policy goes through when pushing one element at a time up to `len`.
+    fn capacities(policy: GrowthPolicy, len: usize) -> Vec<usize> {
+        let mut capacity = 0;
+        let mut seen = Vec::new();
+        for needed in 1..=len {
+            if needed > capacity {
+                capacity = policy.next_capacity(capacity, needed);
+                seen.push(capacity);
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(capacities(GrowthPolicy::Doubling, 20), [4, 8, 16, 32]);
+        assert_eq!(
+            capacities(GrowthPolicy::OneAndHalf, 20),
+            [4, 6, 9, 13, 19, 28]
+        );
+        assert_eq!(capacities(GrowthPolicy::FixedIncrement(5), 12), [5, 10, 15]);
+        assert_eq!(capacities(GrowthPolicy::FixedIncrement(0), 3), [1, 2, 3]);
+        assert_eq!(capacities(GrowthPolicy::Exact, 3), [1, 2, 3]);
+    }
+
+    #[test]
+    fn never_less_than_needed() {
+        for policy in [
+            GrowthPolicy::Doubling,
+            GrowthPolicy::OneAndHalf,
+            GrowthPolicy::FixedIncrement(4),
+            GrowthPolicy::Exact,
+        ] {
+            assert_eq!(policy.next_capacity(8, 100), 100);
+            assert_eq!(policy.next_capacity(usize::MAX - 1, usize::MAX), usize::MAX);
+        }
+    }
+}
@@ -1,97 +1,541 @@
+// The same allocators as Rc, so one can back both.
+pub use pointers::allocator;
+mod growth;
+mod raw;
+mod small;
+
+use allocator::{Allocator, Global};
+use raw::RawNVec;
 use std::{
-    alloc::{self, Layout},
-    ptr::NonNull,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut, Index, IndexMut},
+    ptr,
+    slice::SliceIndex,
 };
 
-pub struct NVec<T> {
-    ptr: NonNull<T>,
+pub use growth::GrowthPolicy;
+pub use raw::{TryReserveError, TryReserveErrorKind};
+pub use small::SmallNVec;
+
+// A is where the elements are allocated, the global allocator unless it's built with `new_in`.
+pub struct NVec<T, A: Allocator = Global> {
+    buf: RawNVec<T, A>,
     len: usize,
-    capacity: usize,
 }
 
 impl<T> NVec<T> {
     pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_in(capacity, Global)
+    }
+
+    // Same as `with_capacity`, but gives back an error instead of panicking or aborting
+    // if the memory can't be had.
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, TryReserveError> {
+        Self::try_with_capacity_in(capacity, Global)
+    }
+}
+
+impl<T, A: Allocator> NVec<T, A> {
+    // Nothing is allocated until the first push, same as `new`.
+    pub fn new_in(alloc: A) -> Self {
         Self {
-            ptr: NonNull::dangling(),
+            buf: RawNVec::new_in(alloc),
             len: 0,
-            capacity: 0,
         }
     }
 
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        let mut vec = Self::new_in(alloc);
+        vec.reserve_exact(capacity);
+        vec
+    }
+
+    pub fn try_with_capacity_in(capacity: usize, alloc: A) -> Result<Self, TryReserveError> {
+        let mut vec = Self::new_in(alloc);
+        vec.try_reserve_exact(capacity)?;
+        Ok(vec)
+    }
+
+    pub fn allocator(&self) -> &A {
+        self.buf.allocator()
+    }
+
+    pub fn growth_policy(&self) -> GrowthPolicy {
+        self.buf.policy()
+    }
+
+    // Changes how the vector grows from here on, the current capacity is left as it is.
+    pub fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+        self.buf.set_policy(policy);
+    }
+
     pub fn capacity(&self) -> usize {
-        self.capacity
+        self.buf.capacity()
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn get(&self, index: usize) -> Option<&T> {
         if index >= self.len {
             return None;
         }
-        Some(unsafe { &*self.ptr.as_ptr().add(index) })
+        Some(unsafe { &*self.ptr().add(index) })
     }
 
     pub fn push(&mut self, to_push: T) {
-        assert_ne!(std::mem::size_of::<T>(), 0, "No zero sized types");
-
-        if self.capacity == 0 {
-            let layout = Layout::array::<T>(4).expect("Not Allocated");
-            // SAFETY: The layout is hardcoded to be 4* size_of<T>.
-            let ptr = unsafe { alloc::alloc(layout) as *mut T };
-            let ptr = NonNull::new(ptr).expect("Couldn't allocate memory");
-            // SAFETY: `ptr` is non-null as already enough memory is allocated.
-            unsafe { ptr.as_ptr().write(to_push) };
-            self.ptr = ptr;
-            self.capacity = 4;
-            self.len += 1;
-        } else if self.len == self.capacity {
-            let new_capacity = self.capacity.checked_mul(2).expect("Capacity wrapped");
-            let size = std::mem::size_of::<T>() * self.capacity;
-            let align = std::mem::align_of::<T>();
-            size.checked_add(size % align).expect("Can't allocate");
-            let ptr = unsafe {
-                let layout = Layout::from_size_align_unchecked(size, align);
-                let new_size = std::mem::size_of::<T>() * new_capacity;
-                let ptr = alloc::realloc(self.ptr.as_ptr() as *mut u8, layout, new_size);
-                let ptr = NonNull::new(ptr as *mut T).expect("Could not reallocate");
-                ptr.as_ptr().add(self.len).write(to_push);
-                ptr
-            };
-            self.ptr = ptr;
-            self.len += 1;
-            self.capacity = new_capacity;
-        } else if self.len < self.capacity {
-            let offset = self
-                .len
-                .checked_mul(std::mem::size_of::<T>())
-                .expect("Cannot reach memory locaiton");
-            assert!(self.len <= (isize::MAX / 2).try_into().unwrap()); // Wrap around check
-            assert!(offset < isize::MAX as usize); // Wrap around check
-                                                   // SAFETY: Memory aloocated is greater than current in used and cannot wrap around
-            unsafe { self.ptr.as_ptr().add(self.len).write(to_push) }
-            self.len += 1;
-        }
-    }
-}
-
-impl<T> Drop for NVec<T> {
+        if self.len == self.capacity() {
+            // Grows as the growth policy says, by default starting at 4 and doubling from there.
+            self.buf.reserve(self.len, 1);
+        }
+        // SAFETY: len < capacity, so the slot is inside the allocation and holds no element.
+        unsafe { self.ptr().add(self.len).write(to_push) };
+        self.len += 1;
+    }
+
+    // Same as `push`, but gives back an error if growing fails. The element is dropped then,
+    // and the vector is left as it was.
+    pub fn try_push(&mut self, to_push: T) -> Result<(), TryReserveError> {
+        if self.len == self.capacity() {
+            self.buf.try_reserve(self.len, 1)?;
+        }
+        // SAFETY: same as `push`.
+        unsafe { self.ptr().add(self.len).write(to_push) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: the slot held the last element, and is outside len now so it's never read again.
+        Some(unsafe { self.ptr().add(self.len).read() })
+    }
+
+    // Shifts everything from index onwards one slot to the right.
+    pub fn insert(&mut self, index: usize, element: T) {
+        assert!(
+            index <= self.len,
+            "insertion index (is {}) should be <= len (is {})",
+            index,
+            self.len
+        );
+        self.reserve(1);
+        // SAFETY: there's room for one more element, index is at most len,
+        // and ptr::copy handles the overlap of the two ranges.
+        unsafe {
+            let at = self.ptr().add(index);
+            ptr::copy(at, at.add(1), self.len - index);
+            at.write(element);
+        }
+        self.len += 1;
+    }
+
+    // Shifts everything after index one slot to the left, O(n).
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "removal index (is {}) should be < len (is {})",
+            index,
+            self.len
+        );
+        // SAFETY: index is in bounds, the element is moved out and its slot overwritten
+        // by the ones after it, so it's never dropped in place.
+        unsafe {
+            let at = self.ptr().add(index);
+            let element = at.read();
+            ptr::copy(at.add(1), at, self.len - index - 1);
+            self.len -= 1;
+            element
+        }
+    }
+
+    // Moves the last element into the hole instead of shifting, O(1) but doesn't keep the order.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(
+            index < self.len,
+            "swap_remove index (is {}) should be < len (is {})",
+            index,
+            self.len
+        );
+        // SAFETY: index is in bounds. If it's the last element, both pointers are the same,
+        // which ptr::copy allows.
+        unsafe {
+            let at = self.ptr().add(index);
+            let element = at.read();
+            ptr::copy(self.ptr().add(self.len - 1), at, 1);
+            self.len -= 1;
+            element
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = ptr::slice_from_raw_parts_mut(
+            // SAFETY: len < self.len, so this is inside the allocation.
+            unsafe { self.ptr().add(len) },
+            self.len - len,
+        );
+        // Shorten first, so if one of the drops panics the vector doesn't still own the
+        // elements that were dropped. drop_in_place keeps dropping the rest while unwinding.
+        self.len = len;
+        // SAFETY: the elements are initialised and no longer part of the vector.
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    // Makes room for at least `additional` more elements, growing by as much as the growth
+    // policy says, so that with the default pushing in a loop stays amortised O(1).
+    pub fn reserve(&mut self, additional: usize) {
+        self.buf.reserve(self.len, additional);
+    }
+
+    // Makes room for exactly `additional` more elements, if there isn't already.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        self.buf.reserve_exact(self.len, additional);
+    }
+
+    // The fallible versions of `reserve` and `reserve_exact`. On error the vector is left as it was.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.buf.try_reserve(self.len, additional)
+    }
+
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        self.buf.try_reserve_exact(self.len, additional)
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to(self.len);
+    }
+
+    fn ptr(&self) -> *mut T {
+        self.buf.ptr()
+    }
+}
+
+impl<T> Default for NVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A: Allocator> Drop for NVec<T, A> {
+    fn drop(&mut self) {
+        // SAFETY: the first len elements are initialised, and nothing touches them after this.
+        // The buffer is freed by RawNVec's Drop afterwards.
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr(), self.len)) };
+    }
+}
+
+// All of the slice API, sort, binary_search, chunks and so on, comes through Deref.
+impl<T, A: Allocator> Deref for NVec<T, A> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        // SAFETY: the first len elements are initialised, and ptr is dangling but aligned
+        // when there's no allocation, which is fine for an empty slice.
+        unsafe { std::slice::from_raw_parts(self.ptr(), self.len) }
+    }
+}
+
+impl<T, A: Allocator> DerefMut for NVec<T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: same as Deref, and we hold the NVec mutably.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr(), self.len) }
+    }
+}
+
+impl<T, I: SliceIndex<[T]>, A: Allocator> Index<I> for NVec<T, A> {
+    type Output = I::Output;
+    fn index(&self, index: I) -> &Self::Output {
+        Index::index(&**self, index)
+    }
+}
+
+impl<T, I: SliceIndex<[T]>, A: Allocator> IndexMut<I> for NVec<T, A> {
+    fn index_mut(&mut self, index: I) -> &mut Self::Output {
+        IndexMut::index_mut(&mut **self, index)
+    }
+}
+
+impl<T: Clone, A: Allocator + Clone> Clone for NVec<T, A> {
+    fn clone(&self) -> Self {
+        let mut vec = NVec::with_capacity_in(self.len, self.allocator().clone());
+        vec.set_growth_policy(self.growth_policy());
+        // Pushed one by one, so if a clone panics the ones already made get dropped.
+        vec.extend(self.iter().cloned());
+        vec
+    }
+}
+
+impl<T: std::fmt::Debug, A: Allocator> std::fmt::Debug for NVec<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: PartialEq<U>, U, A1: Allocator, A2: Allocator> PartialEq<NVec<U, A2>> for NVec<T, A1> {
+    fn eq(&self, other: &NVec<U, A2>) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq, A: Allocator> Eq for NVec<T, A> {}
+
+impl<T: PartialEq<U>, U, A: Allocator> PartialEq<[U]> for NVec<T, A> {
+    fn eq(&self, other: &[U]) -> bool {
+        **self == *other
+    }
+}
+
+impl<T: PartialEq<U>, U, A: Allocator, const N: usize> PartialEq<[U; N]> for NVec<T, A> {
+    fn eq(&self, other: &[U; N]) -> bool {
+        **self == *other
+    }
+}
+
+// Hashes like a slice, so an NVec and a slice with the same elements hash the same.
+impl<T: std::hash::Hash, A: Allocator> std::hash::Hash for NVec<T, A> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T> FromIterator<T> for NVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = NVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T, A: Allocator> Extend<T> for NVec<T, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for element in iter {
+            self.push(element);
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a, A: Allocator> Extend<&'a T> for NVec<T, A> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a NVec<T, A> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T, A: Allocator> IntoIterator for &'a mut NVec<T, A> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, A: Allocator> IntoIterator for NVec<T, A> {
+    type Item = T;
+    type IntoIter = IntoIter<T, A>;
+    fn into_iter(self) -> Self::IntoIter {
+        // The buffer and its elements now belong to the iterator.
+        let vec = ManuallyDrop::new(self);
+        IntoIter {
+            // SAFETY: the NVec is never used or dropped again, so the buffer has one owner.
+            buf: unsafe { ptr::read(&vec.buf) },
+            start: 0,
+            end: vec.len,
+        }
+    }
+}
+
+// Moves the elements out of an NVec one at a time, from either end.
+pub struct IntoIter<T, A: Allocator = Global> {
+    // Dropped after IntoIter's own Drop has dropped the elements that weren't taken.
+    buf: RawNVec<T, A>,
+    // The elements in start..end haven't been taken yet.
+    start: usize,
+    end: usize,
+}
+
+impl<T, A: Allocator> IntoIter<T, A> {
+    // The elements that haven't been taken yet.
+    pub fn as_slice(&self) -> &[T] {
+        // SAFETY: start..end are initialised and still owned by the iterator.
+        unsafe { std::slice::from_raw_parts(self.buf.ptr().add(self.start), self.end - self.start) }
+    }
+}
+
+impl<T, A: Allocator> Iterator for IntoIter<T, A> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        // SAFETY: start < end so the element is initialised, and it's outside start..end
+        // once we move past it, so it's never read or dropped again.
+        let element = unsafe { self.buf.ptr().add(self.start).read() };
+        self.start += 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.start;
+        (len, Some(len))
+    }
+}
+
+impl<T, A: Allocator> DoubleEndedIterator for IntoIter<T, A> {
+    fn next_back(&mut self) -> Option<T> {
+        if self.start == self.end {
+            return None;
+        }
+        self.end -= 1;
+        // SAFETY: same as `next`, from the other end.
+        Some(unsafe { self.buf.ptr().add(self.end).read() })
+    }
+}
+
+impl<T, A: Allocator> ExactSizeIterator for IntoIter<T, A> {}
+
+impl<T, A: Allocator> Drop for IntoIter<T, A> {
     fn drop(&mut self) {
+        // SAFETY: the elements that weren't taken are still initialised, and the iterator
+        // owns them. The buffer is freed by `buf` afterwards.
         unsafe {
-            std::ptr::drop_in_place(std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len));
-            let layout = alloc::Layout::from_size_align_unchecked(
-                std::mem::size_of::<T>() * self.capacity,
-                std::mem::align_of::<T>(),
+            let remaining = ptr::slice_from_raw_parts_mut(
+                self.buf.ptr().add(self.start),
+                self.end - self.start,
             );
-            alloc::dealloc(self.ptr.as_ptr() as *mut u8, layout)
+            ptr::drop_in_place(remaining)
         };
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::NVec;
+    use crate::allocator::{AllocError, Allocator, Global};
+    use crate::{GrowthPolicy, NVec, TryReserveErrorKind};
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::ptr::NonNull;
+
+    // Counts what NVec asks of it, and can be told to fail, so the fallible API can be tested
+    // without running out of memory for real. Hands the calls on to the global allocator.
+    #[derive(Default)]
+    struct TestAlloc {
+        fail: Cell<bool>,
+        allocs: Cell<usize>,
+        grows: Cell<usize>,
+        shrinks: Cell<usize>,
+        frees: Cell<usize>,
+        // Bytes handed out and not given back yet.
+        live: Cell<usize>,
+    }
+
+    impl TestAlloc {
+        fn count(cell: &Cell<usize>) {
+            cell.set(cell.get() + 1);
+        }
+
+        fn check(&self, old: Option<Layout>, new: Layout) -> Result<(), AllocError> {
+            if self.fail.get() {
+                return Err(AllocError);
+            }
+            let old = old.map_or(0, |layout| layout.size());
+            self.live.set(self.live.get() - old + new.size());
+            Ok(())
+        }
+    }
+
+    unsafe impl Allocator for TestAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            self.check(None, layout)?;
+            Self::count(&self.allocs);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            Self::count(&self.frees);
+            self.live.set(self.live.get() - layout.size());
+            Global.deallocate(ptr, layout)
+        }
+
+        unsafe fn grow(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<u8>, AllocError> {
+            self.check(Some(old_layout), new_layout)?;
+            Self::count(&self.grows);
+            Global.grow(ptr, old_layout, new_layout)
+        }
+
+        unsafe fn shrink(
+            &self,
+            ptr: NonNull<u8>,
+            old_layout: Layout,
+            new_layout: Layout,
+        ) -> Result<NonNull<u8>, AllocError> {
+            self.check(Some(old_layout), new_layout)?;
+            Self::count(&self.shrinks);
+            Global.shrink(ptr, old_layout, new_layout)
+        }
+    }
+
+    // Counts its drops, and panics on drop if asked to. SmallNVec's tests use it too.
+    pub(crate) struct Droppable<'a> {
+        drops: &'a Cell<usize>,
+        panic: bool,
+    }
+
+    impl Drop for Droppable<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+            if self.panic {
+                panic!("drop panicked");
+            }
+        }
+    }
+
+    pub(crate) fn droppables(
+        drops: &Cell<usize>,
+        n: usize,
+        panic_at: Option<usize>,
+    ) -> NVec<Droppable<'_>> {
+        let mut vec = NVec::new();
+        for i in 0..n {
+            vec.push(Droppable {
+                drops,
+                panic: Some(i) == panic_at,
+            });
+        }
+        vec
+    }
 
     #[test]
     fn base_test() {
@@ -105,5 +549,439 @@ mod tests {
         assert_eq!(vec.len(), 20);
     }
 
-    // Test for zero sizzed struct
+    #[test]
+    fn pop() {
+        let mut vec = NVec::new();
+        assert_eq!(vec.pop(), None);
+        vec.push(String::from("a"));
+        vec.push(String::from("b"));
+        assert_eq!(vec.pop().as_deref(), Some("b"));
+        assert_eq!(vec.pop().as_deref(), Some("a"));
+        assert_eq!(vec.pop(), None);
+        assert!(vec.is_empty());
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut vec = NVec::new();
+        vec.insert(0, 2);
+        vec.insert(0, 0);
+        vec.insert(1, 1);
+        vec.insert(3, 4);
+        vec.insert(3, 3);
+        assert_eq!(vec, [0, 1, 2, 3, 4]);
+        assert_eq!(vec.remove(0), 0);
+        assert_eq!(vec.remove(3), 4);
+        assert_eq!(vec.remove(1), 2);
+        assert_eq!(vec, [1, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "insertion index (is 2) should be <= len (is 1)")]
+    fn insert_out_of_bounds() {
+        let mut vec = NVec::new();
+        vec.push(1);
+        vec.insert(2, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "removal index (is 1) should be < len (is 1)")]
+    fn remove_out_of_bounds() {
+        let mut vec = NVec::new();
+        vec.push(1);
+        vec.remove(1);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut vec = NVec::new();
+        for i in 0..5 {
+            vec.push(i);
+        }
+        assert_eq!(vec.swap_remove(1), 1);
+        assert_eq!(vec, [0, 4, 2, 3]);
+        assert_eq!(vec.swap_remove(3), 3);
+        assert_eq!(vec, [0, 4, 2]);
+    }
+
+    #[test]
+    fn truncate_clear() {
+        let drops = Cell::new(0);
+        let mut vec = droppables(&drops, 10, None);
+        vec.truncate(20);
+        assert_eq!(drops.get(), 0);
+        vec.truncate(6);
+        assert_eq!((vec.len(), drops.get()), (6, 4));
+        drop(vec.remove(0));
+        drop(vec.swap_remove(0));
+        assert_eq!((vec.len(), drops.get()), (4, 6));
+        vec.clear();
+        assert_eq!((vec.len(), drops.get()), (0, 10));
+        assert_eq!(vec.capacity(), 16);
+        drop(vec);
+        assert_eq!(drops.get(), 10);
+    }
+
+    #[test]
+    fn truncate_panicking_drop() {
+        let drops = Cell::new(0);
+        let mut vec = droppables(&drops, 10, Some(5));
+        assert!(catch_unwind(AssertUnwindSafe(|| vec.truncate(2))).is_err());
+        // Every element past the new len got dropped once, even the ones after the panic.
+        assert_eq!((vec.len(), drops.get()), (2, 8));
+        drop(vec);
+        assert_eq!(drops.get(), 10);
+    }
+
+    #[test]
+    fn drop_panicking_drop() {
+        let drops = Cell::new(0);
+        let vec = droppables(&drops, 10, Some(0));
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(vec))).is_err());
+        assert_eq!(drops.get(), 10);
+    }
+
+    #[test]
+    fn reserve() {
+        let mut vec = NVec::with_capacity(3);
+        assert_eq!(vec.capacity(), 3);
+        vec.push(1);
+        vec.reserve(2);
+        assert_eq!(vec.capacity(), 3);
+        vec.reserve(3);
+        assert_eq!(vec.capacity(), 6);
+        vec.reserve_exact(10);
+        assert_eq!(vec.capacity(), 11);
+        vec.reserve_exact(1);
+        assert_eq!(vec.capacity(), 11);
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 1);
+        assert_eq!(vec.get(0), Some(&1));
+        vec.clear();
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 0);
+        vec.push(2);
+        assert_eq!(vec.capacity(), 4);
+        assert_eq!(NVec::<u8>::with_capacity(0).capacity(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capacity wrapped")]
+    fn reserve_overflow() {
+        let mut vec = NVec::<u8>::new();
+        vec.push(1);
+        vec.reserve(usize::MAX);
+    }
+
+    #[test]
+    fn slice_api() {
+        let mut vec: NVec<i32> = [5, 3, 1, 4, 2].into_iter().collect();
+        vec.sort();
+        assert_eq!(vec, [1, 2, 3, 4, 5]);
+        assert_eq!(vec.binary_search(&4), Ok(3));
+        assert_eq!(vec.chunks(2).count(), 3);
+        assert_eq!(vec[1..3], [2, 3]);
+        vec[0] = 10;
+        assert_eq!(vec[0], 10);
+        assert_eq!(vec.first(), Some(&10));
+        assert_eq!(*NVec::<i32>::new(), []);
+    }
+
+    #[test]
+    #[should_panic(expected = "index out of bounds: the len is 3 but the index is 3")]
+    fn index_out_of_bounds() {
+        let vec: NVec<i32> = (0..3).collect();
+        let _ = vec[3];
+    }
+
+    #[test]
+    fn iterators() {
+        let mut vec: NVec<i32> = (1..=4).collect();
+        for x in &mut vec {
+            *x *= 2;
+        }
+        assert_eq!((&vec).into_iter().sum::<i32>(), 20);
+        vec.extend([10, 12].iter());
+        vec.extend(vec![14]);
+        let mut owned = vec.into_iter();
+        assert_eq!(owned.len(), 7);
+        assert_eq!(owned.next(), Some(2));
+        assert_eq!(owned.next_back(), Some(14));
+        assert_eq!(owned.as_slice(), [4, 6, 8, 10, 12]);
+        assert_eq!(owned.rev().collect::<Vec<_>>(), [12, 10, 8, 6, 4]);
+    }
+
+    #[test]
+    fn into_iter_drops_rest() {
+        let drops = Cell::new(0);
+        let mut owned = droppables(&drops, 5, None).into_iter();
+        drop(owned.next());
+        drop(owned.next_back());
+        assert_eq!(drops.get(), 2);
+        drop(owned);
+        assert_eq!(drops.get(), 5);
+        // Nothing taken at all, and a panicking drop in the middle.
+        let drops = Cell::new(0);
+        let owned = droppables(&drops, 5, Some(2)).into_iter();
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(owned))).is_err());
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn clone_eq_hash_debug() {
+        use std::hash::{BuildHasher, RandomState};
+        let vec: NVec<String> = ["a", "b"].iter().map(|s| s.to_string()).collect();
+        let cloned = vec.clone();
+        assert_eq!(vec, cloned);
+        assert_ne!(vec, NVec::<String>::new());
+        assert_eq!(format!("{:?}", cloned), r#"["a", "b"]"#);
+        let state = RandomState::new();
+        assert_eq!(state.hash_one(&vec), state.hash_one(&cloned));
+        assert_eq!(
+            state.hash_one(&vec),
+            state.hash_one(&["a", "b"].map(String::from)[..])
+        );
+    }
+
+    #[test]
+    fn try_reserve() {
+        let mut vec: NVec<u32> = (0..3).collect();
+        assert_eq!(vec.try_reserve(10), Ok(()));
+        assert_eq!(vec.capacity(), 13);
+        assert_eq!(vec.try_reserve_exact(10), Ok(()));
+        assert_eq!(vec.capacity(), 13);
+        let e = vec.try_reserve(usize::MAX).unwrap_err();
+        assert_eq!(e.kind(), TryReserveErrorKind::CapacityOverflow);
+        let e = vec.try_reserve_exact(isize::MAX as usize / 4).unwrap_err();
+        assert_eq!(e.kind(), TryReserveErrorKind::CapacityOverflow);
+        assert_eq!(vec, [0, 1, 2]);
+        assert_eq!(vec.capacity(), 13);
+    }
+
+    #[test]
+    fn try_reserve_alloc_error() {
+        let alloc = TestAlloc::default();
+        let mut vec = NVec::new_in(&alloc);
+        vec.extend(0..3u32);
+        alloc.fail.set(true);
+        let e = vec.try_reserve_exact(7).unwrap_err();
+        assert_eq!(
+            e.kind(),
+            TryReserveErrorKind::AllocError {
+                layout: Layout::array::<u32>(10).unwrap()
+            }
+        );
+        assert_eq!(
+            e.to_string(),
+            "memory allocation failed because the memory allocator returned an error"
+        );
+        // The old buffer is still there, untouched.
+        assert_eq!(vec, [0, 1, 2]);
+        assert_eq!(vec.capacity(), 4);
+        assert!(vec.try_reserve(1).is_ok());
+        let e = NVec::<u8, _>::try_with_capacity_in(1, &alloc).unwrap_err();
+        assert!(matches!(e.kind(), TryReserveErrorKind::AllocError { .. }));
+        alloc.fail.set(false);
+        assert_eq!(NVec::<u8>::try_with_capacity(5).unwrap().capacity(), 5);
+    }
+
+    #[test]
+    fn try_push() {
+        let drops = Cell::new(0);
+        let alloc = TestAlloc::default();
+        let mut vec = NVec::new_in(&alloc);
+        let mut push = || {
+            vec.try_push(Droppable {
+                drops: &drops,
+                panic: false,
+            })
+        };
+        for _ in 0..3 {
+            assert!(push().is_ok());
+        }
+        alloc.fail.set(true);
+        // Still room for one more without allocating.
+        assert!(push().is_ok());
+        let e = push().unwrap_err();
+        assert!(matches!(e.kind(), TryReserveErrorKind::AllocError { .. }));
+        alloc.fail.set(false);
+        assert!(push().is_ok());
+        // The element that couldn't be pushed was dropped, the rest are still there.
+        assert_eq!((vec.len(), drops.get()), (5, 1));
+        assert_eq!(vec.capacity(), 8);
+        drop(vec);
+        assert_eq!(drops.get(), 6);
+    }
+
+    // The same pushes as the nvec binary: one allocation of 4, then a grow for every doubling.
+    #[test]
+    fn allocator_growth_sequence() {
+        // The same pushes as the nvec binary, for each policy it reports on: how many times
+        // the buffer grows and the capacity it ends up with.
+        for (policy, grows, capacity) in [
+            // 4 -> 8 -> 16 -> ... -> 1024
+            (GrowthPolicy::Doubling, 8, 1024),
+            // 4 -> 6 -> 9 -> ... -> 711 -> 1066
+            (GrowthPolicy::OneAndHalf, 14, 1066),
+            // 64 -> 128 -> ... -> 1024
+            (GrowthPolicy::FixedIncrement(64), 15, 1024),
+            // 1 -> 2 -> ... -> 1000
+            (GrowthPolicy::Exact, 999, 1000),
+        ] {
+            let alloc = TestAlloc::default();
+            let mut vec = NVec::new_in(&alloc);
+            vec.set_growth_policy(policy);
+            assert_eq!(alloc.allocs.get(), 0);
+            for i in 1..=1000 {
+                vec.push(i);
+            }
+            assert_eq!(vec.capacity(), capacity, "{:?}", policy);
+            // Only the first push allocates, every later change of capacity is a grow.
+            assert_eq!(alloc.allocs.get(), 1, "{:?}", policy);
+            assert_eq!(alloc.grows.get(), grows, "{:?}", policy);
+            assert_eq!(alloc.live.get(), capacity * std::mem::size_of::<i32>());
+            drop(vec);
+            assert_eq!(alloc.frees.get(), 1);
+            assert_eq!(alloc.live.get(), 0);
+        }
+    }
+
+    #[test]
+    fn allocator_shared_with_rc() {
+        // The same allocator can back an Rc as well as an NVec.
+        let alloc = TestAlloc::default();
+        let mut vec = NVec::new_in(&alloc);
+        vec.push(1);
+        let rc = pointers::rc::Rc::new_in(2, &alloc);
+        assert_eq!(alloc.allocs.get(), 2);
+        drop(vec);
+        drop(rc);
+        assert_eq!(alloc.frees.get(), 2);
+        assert_eq!(alloc.live.get(), 0);
+    }
+
+    #[test]
+    fn growth_policy() {
+        let mut vec = NVec::new();
+        assert_eq!(vec.growth_policy(), GrowthPolicy::Doubling);
+        vec.set_growth_policy(GrowthPolicy::OneAndHalf);
+        for i in 0..10 {
+            vec.push(i);
+        }
+        assert_eq!(vec.capacity(), 13);
+        vec.set_growth_policy(GrowthPolicy::FixedIncrement(10));
+        vec.reserve(4);
+        assert_eq!(vec.capacity(), 23);
+        vec.set_growth_policy(GrowthPolicy::Exact);
+        vec.extend(10..24);
+        assert_eq!(vec.capacity(), 24);
+        // A clone grows the same way.
+        let mut cloned = vec.clone();
+        assert_eq!(cloned.growth_policy(), GrowthPolicy::Exact);
+        cloned.push(24);
+        assert_eq!(cloned.capacity(), 25);
+        // reserve_exact ignores the policy.
+        vec.set_growth_policy(GrowthPolicy::Doubling);
+        vec.reserve_exact(1);
+        assert_eq!(vec.capacity(), 25);
+        assert!(vec.iter().copied().eq(0..24));
+    }
+
+    #[test]
+    fn allocator_shrink_clone_into_iter() {
+        let alloc = TestAlloc::default();
+        let mut vec = NVec::with_capacity_in(10, &alloc);
+        vec.extend([1u64, 2, 3]);
+        vec.shrink_to_fit();
+        assert_eq!(alloc.shrinks.get(), 1);
+        assert_eq!(alloc.live.get(), 3 * 8);
+        // The clone is allocated from the same allocator.
+        let cloned = vec.clone();
+        assert_eq!(alloc.allocs.get(), 2);
+        assert!(std::ptr::eq(*cloned.allocator(), &alloc));
+        assert_eq!(cloned.into_iter().sum::<u64>(), 6);
+        vec.clear();
+        vec.shrink_to_fit();
+        assert_eq!(alloc.frees.get(), 2);
+        assert_eq!(alloc.live.get(), 0);
+        // Zero sized types never reach the allocator.
+        let mut units = NVec::new_in(&alloc);
+        units.extend([(), (), ()]);
+        drop(units);
+        assert_eq!((alloc.allocs.get(), alloc.frees.get()), (2, 2));
+    }
+
+    #[test]
+    fn try_push_zst_overflow() {
+        let mut vec = NVec::new();
+        vec.len = usize::MAX;
+        let e = vec.try_push(()).unwrap_err();
+        assert_eq!(e.kind(), TryReserveErrorKind::CapacityOverflow);
+        vec.len = 0;
+    }
+
+    #[test]
+    fn zst() {
+        let mut vec = NVec::new();
+        assert_eq!(vec.capacity(), usize::MAX);
+        for _ in 0..1000 {
+            vec.push(());
+        }
+        assert_eq!(vec.len(), 1000);
+        assert_eq!(vec.get(999), Some(&()));
+        assert_eq!(vec.get(1000), None);
+        assert_eq!(vec.pop(), Some(()));
+        vec.insert(0, ());
+        vec.remove(500);
+        vec.swap_remove(0);
+        assert_eq!(vec.len(), 998);
+        vec.reserve(usize::MAX - 998);
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), usize::MAX);
+        assert_eq!(vec.iter().count(), 998);
+        assert_eq!(vec.into_iter().rev().count(), 998);
+        assert_eq!(NVec::<()>::with_capacity(10).capacity(), usize::MAX);
+    }
+
+    #[test]
+    fn zst_empty_struct() {
+        #[derive(Clone, PartialEq, Debug)]
+        struct Empty;
+        let vec: NVec<Empty> = (0..10).map(|_| Empty).collect();
+        assert_eq!(vec.clone(), vec);
+        assert_eq!(vec[9], Empty);
+        assert_eq!(format!("{:?}", &vec[..2]), "[Empty, Empty]");
+    }
+
+    #[test]
+    fn zst_drops() {
+        // A zero sized type can't hold a reference to a counter, so it counts in a thread local.
+        thread_local!(static DROPS: Cell<usize> = const { Cell::new(0) });
+        struct Counted;
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.with(|d| d.set(d.get() + 1));
+            }
+        }
+        let mut vec = NVec::new();
+        for _ in 0..10 {
+            vec.push(Counted);
+        }
+        drop(vec.pop());
+        vec.truncate(5);
+        assert_eq!(DROPS.with(Cell::get), 5);
+        let mut owned = vec.into_iter();
+        drop(owned.next());
+        assert_eq!(DROPS.with(Cell::get), 6);
+        drop(owned);
+        assert_eq!(DROPS.with(Cell::get), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capacity wrapped")]
+    fn zst_overflow() {
+        let mut vec = NVec::new();
+        vec.len = usize::MAX;
+        vec.push(());
+    }
 }
@@ -1,14 +1,48 @@
 // For a binary to used with valgrind
 
-use nvec::NVec;
+use nvec::{GrowthPolicy, NVec};
 
-fn main() {
+// Pushes 1..=1000 and counts how many times the buffer had to be reallocated on the way,
+// the first allocation doesn't count since every policy makes it.
+fn push_all(policy: GrowthPolicy) -> (NVec<i32>, usize) {
     let mut vec = NVec::new();
     // let mut vec =  Vec::new();
+    vec.set_growth_policy(policy);
+    let mut reallocations = 0;
     for i in 1..=1000 {
-        vec.push(i)
+        let capacity = vec.capacity();
+        vec.push(i);
+        if capacity != 0 && vec.capacity() != capacity {
+            reallocations += 1;
+        }
     }
+    (vec, reallocations)
+}
 
+fn main() {
+    let (vec, _) = push_all(GrowthPolicy::default());
     assert_eq!(vec.capacity(), 1024); // Allocated on the heap of size 2^n
     assert_eq!(vec.len(), 1000);
+
+    println!(
+        "{:<20} {:>13} {:>9} {:>14}",
+        "policy", "reallocations", "capacity", "wasted slots"
+    );
+    for policy in [
+        GrowthPolicy::Doubling,
+        GrowthPolicy::OneAndHalf,
+        GrowthPolicy::FixedIncrement(64),
+        GrowthPolicy::Exact,
+    ] {
+        let (vec, reallocations) = push_all(policy);
+        // Slots allocated but never filled.
+        let wasted = vec.capacity() - vec.len();
+        println!(
+            "{:<20} {:>13} {:>9} {:>14}",
+            format!("{:?}", policy),
+            reallocations,
+            vec.capacity(),
+            wasted
+        );
+    }
 }
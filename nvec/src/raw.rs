@@ -0,0 +1,260 @@
+use crate::allocator::{Allocator, Global};
+use crate::growth::GrowthPolicy;
+use std::{
+    alloc::{self, Layout},
+    ptr::NonNull,
+};
+
+// The buffer behind an NVec, a pointer and how many T's fit in it. It only allocates, grows and
+// frees, it doesn't know which slots hold elements, that's up to whoever owns it.
+// Zero sized types never allocate, the capacity is usize::MAX and the pointer stays dangling.
+// A is where the buffer is allocated, it's never asked for a zero sized block.
+pub(crate) struct RawNVec<T, A: Allocator = Global> {
+    ptr: NonNull<T>,
+    capacity: usize,
+    alloc: A,
+    policy: GrowthPolicy,
+}
+
+impl<T, A: Allocator> RawNVec<T, A> {
+    const IS_ZST: bool = std::mem::size_of::<T>() == 0;
+
+    pub(crate) fn new_in(alloc: A) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            // Zero sized types take no memory, so any number of them fit without allocating.
+            capacity: if Self::IS_ZST { usize::MAX } else { 0 },
+            alloc,
+            policy: GrowthPolicy::default(),
+        }
+    }
+
+    pub(crate) fn ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+
+    pub(crate) fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn policy(&self) -> GrowthPolicy {
+        self.policy
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: GrowthPolicy) {
+        self.policy = policy;
+    }
+
+    // Makes room for at least `additional` more elements after the first `len`, growing by as
+    // much as the policy says, so that with the default pushing in a loop stays amortised O(1).
+    pub(crate) fn try_reserve(
+        &mut self,
+        len: usize,
+        additional: usize,
+    ) -> Result<(), TryReserveError> {
+        let needed = len
+            .checked_add(additional)
+            .ok_or(TryReserveError::overflow())?;
+        if needed <= self.capacity {
+            return Ok(());
+        }
+        self.try_set_capacity(self.policy.next_capacity(self.capacity, needed))
+    }
+
+    // Makes room for exactly `additional` more elements after the first `len`, if there isn't already.
+    pub(crate) fn try_reserve_exact(
+        &mut self,
+        len: usize,
+        additional: usize,
+    ) -> Result<(), TryReserveError> {
+        let needed = len
+            .checked_add(additional)
+            .ok_or(TryReserveError::overflow())?;
+        if needed <= self.capacity {
+            return Ok(());
+        }
+        self.try_set_capacity(needed)
+    }
+
+    pub(crate) fn reserve(&mut self, len: usize, additional: usize) {
+        handle_reserve(self.try_reserve(len, additional))
+    }
+
+    pub(crate) fn reserve_exact(&mut self, len: usize, additional: usize) {
+        handle_reserve(self.try_reserve_exact(len, additional))
+    }
+
+    // Gives back the slots past `len`, the elements in them must already be gone.
+    pub(crate) fn shrink_to(&mut self, len: usize) {
+        if len < self.capacity {
+            handle_reserve(self.try_set_capacity(len))
+        }
+    }
+
+    // Moves the buffer to an allocation for exactly `new_capacity` elements, or frees it if
+    // that's 0. The caller makes sure no element lives past new_capacity.
+    fn try_set_capacity(&mut self, new_capacity: usize) -> Result<(), TryReserveError> {
+        if Self::IS_ZST {
+            return Ok(());
+        }
+
+        if new_capacity == 0 {
+            // SAFETY: capacity isn't 0 (it's bigger than new_capacity), so ptr came from an
+            // allocation with this layout.
+            unsafe {
+                self.alloc
+                    .deallocate(self.ptr.cast(), self.current_layout())
+            };
+            self.ptr = NonNull::dangling();
+        } else {
+            let new_layout =
+                Layout::array::<T>(new_capacity).map_err(|_| TryReserveError::overflow())?;
+            let ptr = if self.capacity == 0 {
+                self.alloc.allocate(new_layout)
+            } else if new_capacity > self.capacity {
+                // SAFETY: ptr came from an allocation with the old layout, the alignment stays
+                // the same and Layout::array checked that the new size fits in isize::MAX.
+                unsafe {
+                    self.alloc
+                        .grow(self.ptr.cast(), self.current_layout(), new_layout)
+                }
+            } else {
+                // SAFETY: same as grow, and the new layout isn't zero sized.
+                unsafe {
+                    self.alloc
+                        .shrink(self.ptr.cast(), self.current_layout(), new_layout)
+                }
+            };
+            // On failure the old allocation is untouched and still ours.
+            self.ptr = ptr
+                .map_err(|_| TryReserveError {
+                    kind: TryReserveErrorKind::AllocError { layout: new_layout },
+                })?
+                .cast();
+        }
+        self.capacity = new_capacity;
+        Ok(())
+    }
+
+    fn current_layout(&self) -> Layout {
+        Layout::array::<T>(self.capacity)
+            .expect("the buffer was allocated with this layout, so it can't overflow")
+    }
+}
+
+// The infallible API panics on overflow and goes to the global handler when memory runs out,
+// same as std.
+fn handle_reserve(result: Result<(), TryReserveError>) {
+    match result.map_err(|e| e.kind) {
+        Ok(()) => {}
+        Err(TryReserveErrorKind::CapacityOverflow) => panic!("Capacity wrapped"),
+        Err(TryReserveErrorKind::AllocError { layout }) => alloc::handle_alloc_error(layout),
+    }
+}
+
+impl<T, A: Allocator> Drop for RawNVec<T, A> {
+    // Only frees the buffer, whoever owns the elements drops them first. Since this is a field,
+    // it still runs if one of their drops panics.
+    fn drop(&mut self) {
+        if self.capacity != 0 && !Self::IS_ZST {
+            // SAFETY: ptr came from an allocation with this layout.
+            unsafe {
+                self.alloc
+                    .deallocate(self.ptr.cast(), self.current_layout())
+            };
+        }
+    }
+}
+
+// Why growing an NVec failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TryReserveError {
+    kind: TryReserveErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TryReserveErrorKind {
+    // The capacity asked for doesn't fit in a usize, or its size in bytes doesn't fit in isize::MAX.
+    CapacityOverflow,
+    // The allocator couldn't hand out memory for this layout.
+    AllocError { layout: Layout },
+}
+
+impl TryReserveError {
+    fn overflow() -> Self {
+        TryReserveError {
+            kind: TryReserveErrorKind::CapacityOverflow,
+        }
+    }
+
+    pub fn kind(&self) -> TryReserveErrorKind {
+        self.kind.clone()
+    }
+}
+
+impl std::fmt::Display for TryReserveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("memory allocation failed")?;
+        match self.kind {
+            TryReserveErrorKind::CapacityOverflow => {
+                f.write_str(" because the computed capacity exceeded the collection's maximum")
+            }
+            TryReserveErrorKind::AllocError { .. } => {
+                f.write_str(" because the memory allocator returned an error")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TryReserveError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawNVec, TryReserveErrorKind};
+    use crate::allocator::Global;
+
+    #[test]
+    fn growth() {
+        let mut buf = RawNVec::<u32>::new_in(Global);
+        assert_eq!(buf.capacity(), 0);
+        buf.reserve(0, 1);
+        assert_eq!(buf.capacity(), 4);
+        buf.reserve(4, 1);
+        assert_eq!(buf.capacity(), 8);
+        buf.reserve(8, 20);
+        assert_eq!(buf.capacity(), 28);
+        buf.reserve_exact(28, 2);
+        assert_eq!(buf.capacity(), 30);
+        buf.shrink_to(0);
+        assert_eq!(buf.capacity(), 0);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buf = RawNVec::<u64>::new_in(Global);
+        // Doesn't fit in a usize.
+        let e = buf.try_reserve(1, usize::MAX).unwrap_err();
+        assert_eq!(e.kind(), TryReserveErrorKind::CapacityOverflow);
+        // Fits in a usize, but not in isize::MAX bytes.
+        let e = buf
+            .try_reserve_exact(0, isize::MAX as usize / 8 + 1)
+            .unwrap_err();
+        assert_eq!(e.kind(), TryReserveErrorKind::CapacityOverflow);
+        assert_eq!(
+            e.to_string(),
+            "memory allocation failed because the computed capacity exceeded the collection's maximum"
+        );
+        // Nothing was allocated along the way.
+        assert_eq!(buf.capacity(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Capacity wrapped")]
+    fn overflow_panics() {
+        RawNVec::<u8>::new_in(Global).reserve_exact(0, isize::MAX as usize + 1);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::NVec;
+use std::{
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+// An NVec that keeps its first N elements inline, and only goes to the heap once it outgrows them.
+// +No allocation at all for vectors that stay small
+// -Bigger than an NVec by N slots, even once it has spilled
+// -Every access checks where the elements are
+pub struct SmallNVec<T, const N: usize> {
+    data: Data<T, N>,
+}
+
+enum Data<T, const N: usize> {
+    // The first len slots are initialised.
+    Inline {
+        buf: [MaybeUninit<T>; N],
+        len: usize,
+    },
+    // Once spilled the elements stay on the heap, even if enough are popped to fit inline again.
+    Heap(NVec<T>),
+}
+
+impl<T, const N: usize> SmallNVec<T, N> {
+    pub fn new() -> Self {
+        Self {
+            data: Data::Inline {
+                buf: [const { MaybeUninit::uninit() }; N],
+                len: 0,
+            },
+        }
+    }
+
+    // Whether the elements have moved to the heap.
+    pub fn spilled(&self) -> bool {
+        matches!(self.data, Data::Heap(_))
+    }
+
+    pub fn capacity(&self) -> usize {
+        match &self.data {
+            Data::Inline { .. } => N,
+            Data::Heap(vec) => vec.capacity(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            Data::Inline { len, .. } => *len,
+            Data::Heap(vec) => vec.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        (**self).get(index)
+    }
+
+    pub fn push(&mut self, to_push: T) {
+        match &mut self.data {
+            Data::Inline { buf, len } if *len < N => {
+                buf[*len].write(to_push);
+                *len += 1;
+            }
+            Data::Inline { .. } => {
+                let mut heap = self.spill();
+                // The heap has exactly N slots, so this grows it the way any NVec grows.
+                heap.push(to_push);
+                self.data = Data::Heap(heap);
+            }
+            Data::Heap(vec) => vec.push(to_push),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        match &mut self.data {
+            Data::Inline { buf, len } => {
+                if *len == 0 {
+                    return None;
+                }
+                *len -= 1;
+                // SAFETY: the slot held the last element, and is outside len now so it's
+                // never read or dropped again.
+                Some(unsafe { buf[*len].assume_init_read() })
+            }
+            Data::Heap(vec) => vec.pop(),
+        }
+    }
+
+    // Moves the inline elements into an NVec with room for exactly N.
+    fn spill(&mut self) -> NVec<T> {
+        let Data::Inline { buf, len } = &mut self.data else {
+            unreachable!("only inline elements spill");
+        };
+        let mut heap = NVec::with_capacity(N);
+        // Forget the inline elements first, so they're never dropped twice. Pushing can't
+        // panic here since there's room for all of them, but if it did they'd only leak.
+        let count = std::mem::replace(len, 0);
+        for slot in &buf[..count] {
+            // SAFETY: the first count slots are initialised, and each is read exactly once.
+            heap.push(unsafe { slot.assume_init_read() });
+        }
+        heap
+    }
+}
+
+impl<T, const N: usize> Default for SmallNVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for SmallNVec<T, N> {
+    fn drop(&mut self) {
+        // A spilled NVec drops its own elements.
+        if let Data::Inline { buf, len } = &mut self.data {
+            let elements = ptr::slice_from_raw_parts_mut(buf.as_mut_ptr() as *mut T, *len);
+            // SAFETY: the first len slots are initialised, and nothing touches them after this.
+            unsafe { ptr::drop_in_place(elements) };
+        }
+    }
+}
+
+impl<T, const N: usize> Deref for SmallNVec<T, N> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        match &self.data {
+            // SAFETY: the first len slots are initialised, and MaybeUninit<T> has T's layout.
+            Data::Inline { buf, len } => unsafe {
+                std::slice::from_raw_parts(buf.as_ptr() as *const T, *len)
+            },
+            Data::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T, const N: usize> DerefMut for SmallNVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match &mut self.data {
+            // SAFETY: same as Deref, and we hold the SmallNVec mutably.
+            Data::Inline { buf, len } => unsafe {
+                std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut T, *len)
+            },
+            Data::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T: std::fmt::Debug, const N: usize> std::fmt::Debug for SmallNVec<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SmallNVec;
+    use crate::tests::droppables;
+    use std::cell::Cell;
+
+    #[test]
+    fn inline_then_spilled() {
+        let mut vec = SmallNVec::<i32, 8>::new();
+        for i in 0..8 {
+            vec.push(i);
+        }
+        assert!(!vec.spilled());
+        assert_eq!(vec.capacity(), 8);
+        assert_eq!(vec.len(), 8);
+        vec.push(8);
+        assert!(vec.spilled());
+        // Spilled into N slots, which then doubled.
+        assert_eq!(vec.capacity(), 16);
+        for i in 9..20 {
+            vec.push(i);
+        }
+        assert_eq!(vec.capacity(), 32);
+        assert_eq!(vec.len(), 20);
+        for i in 0..20 {
+            assert_eq!(vec.get(i as usize), Some(&i));
+        }
+        assert_eq!(vec.get(20), None);
+        assert_eq!(vec.pop(), Some(19));
+    }
+
+    #[test]
+    fn pop_and_slice() {
+        let mut vec = SmallNVec::<String, 2>::default();
+        assert_eq!(vec.pop(), None);
+        vec.push(String::from("b"));
+        vec.push(String::from("a"));
+        vec.sort();
+        assert_eq!(format!("{:?}", vec), r#"["a", "b"]"#);
+        assert_eq!(vec.pop().as_deref(), Some("b"));
+        assert_eq!(vec.len(), 1);
+        assert!(!vec.is_empty());
+    }
+
+    #[test]
+    fn zero_inline() {
+        let mut vec = SmallNVec::<u8, 0>::new();
+        assert_eq!(vec.capacity(), 0);
+        assert!(!vec.spilled());
+        vec.push(1);
+        assert!(vec.spilled());
+        assert_eq!(vec.capacity(), 4);
+        assert_eq!(*vec, [1]);
+    }
+
+    #[test]
+    fn drops() {
+        let drops = Cell::new(0);
+        let mut vec = SmallNVec::<_, 4>::new();
+        for droppable in droppables(&drops, 3, None) {
+            vec.push(droppable);
+        }
+        drop(vec.pop());
+        assert_eq!(drops.get(), 1);
+        drop(vec);
+        assert_eq!(drops.get(), 3);
+
+        let drops = Cell::new(0);
+        let mut vec = SmallNVec::<_, 4>::new();
+        for droppable in droppables(&drops, 6, None) {
+            vec.push(droppable);
+        }
+        // Moving the inline elements to the heap doesn't drop them.
+        assert!(vec.spilled());
+        assert_eq!(drops.get(), 0);
+        drop(vec);
+        assert_eq!(drops.get(), 6);
+    }
+}
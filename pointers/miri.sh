@@ -0,0 +1,15 @@
+#!/bin/sh
+# Runs the tests under Miri, which catches undefined behaviour in the unsafe code that the
+# tests themselves can't see. Needs a nightly toolchain with Miri:
+#
+#     rustup component add --toolchain nightly miri
+#
+# Miri can't spawn processes, so the tests that check for an abort are skipped.
+# The tests that leak on purpose are skipped too, and run on their own with leaks ignored.
+# Extra arguments go to cargo test, e.g. `./miri.sh rc::tests`.
+set -e
+cd "$(dirname "$0")"
+cargo +nightly miri test "$@"
+cargo +nightly miri test --features debug-borrows "$@"
+MIRIFLAGS="$MIRIFLAGS -Zmiri-ignore-leaks" cargo +nightly miri test --lib -- --ignored --exact \
+    rc::tests::leak rc::tests::forget_many rc::tests::new_cyclic_panic_kept_weak
@@ -0,0 +1,177 @@
+use std::alloc::{self, Layout};
+use std::ptr::{self, NonNull};
+
+// A stand-in for std's unstable `Allocator` trait, so Rc can be given an allocator on stable.
+// nvec uses it for NVec's buffer too, so one allocator can back both. Only the calls they need,
+// with the same contracts as the std ones.
+/// # Safety
+/// Implementors must hand out memory that fits the layout and stays valid until it's given back
+/// through `deallocate`, `grow` or `shrink` on the same allocator, or on a clone of it.
+pub unsafe trait Allocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Gives memory back to the allocator.
+    ///
+    /// # Safety
+    /// `ptr` must come from this allocator (or a clone of it) with the same `layout`,
+    /// and must not be used afterwards.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Moves the memory to a bigger block, keeping its contents. The default allocates the new
+    /// block and copies over, allocators that can grow in place should override it.
+    /// On error the old block is untouched and still owned by the caller.
+    ///
+    /// # Safety
+    /// Same as `deallocate` for `ptr` and `old_layout`, and `new_layout` must have the same
+    /// alignment and be at least as big.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr(), old_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+
+    /// Moves the memory to a smaller block, keeping as much of its contents as fits.
+    /// On error the old block is untouched and still owned by the caller.
+    ///
+    /// # Safety
+    /// Same as `grow`, but `new_layout` must be at most as big, and not zero sized.
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new = self.allocate(new_layout)?;
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new.as_ptr(), new_layout.size());
+        self.deallocate(ptr, old_layout);
+        Ok(new)
+    }
+}
+
+// Returned when an allocator can't hand out the memory asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl std::fmt::Display for AllocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl std::error::Error for AllocError {}
+
+// The global allocator, what Box and Vec use.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+impl Global {
+    // The global allocator can't be asked for nothing, a dangling aligned pointer will do.
+    fn dangling(layout: Layout) -> NonNull<u8> {
+        NonNull::new(ptr::without_provenance_mut(layout.align())).expect("align is never 0")
+    }
+}
+
+unsafe impl Allocator for Global {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(Global::dangling(layout));
+        }
+        // SAFETY: the layout isn't zero sized.
+        NonNull::new(unsafe { alloc::alloc(layout) }).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            alloc::dealloc(ptr.as_ptr(), layout)
+        }
+    }
+
+    // realloc can often grow or shrink in place, without copying.
+    // It can't go from or to zero sized blocks though, those were never really allocated.
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+        NonNull::new(alloc::realloc(ptr.as_ptr(), old_layout, new_layout.size())).ok_or(AllocError)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(Global::dangling(new_layout));
+        }
+        NonNull::new(alloc::realloc(ptr.as_ptr(), old_layout, new_layout.size())).ok_or(AllocError)
+    }
+}
+
+// A reference to an allocator is an allocator too, so one allocator can back many Rc's or NVec's.
+unsafe impl<A: Allocator + ?Sized> Allocator for &A {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        (**self).grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        (**self).shrink(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Allocator, Global};
+    use std::alloc::Layout;
+
+    #[test]
+    fn global_zero_sized() {
+        let empty = Layout::from_size_align(0, 16).unwrap();
+        let ptr = Global.allocate(empty).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % 16, 0);
+        // SAFETY: ptr came from Global with this layout, and isn't used again.
+        unsafe { Global.deallocate(ptr, empty) };
+
+        // Growing from nothing and shrinking back to nothing.
+        let full = Layout::from_size_align(32, 16).unwrap();
+        let ptr = Global.allocate(empty).unwrap();
+        // SAFETY: each pointer came from Global with the old layout, and isn't used again.
+        unsafe {
+            let ptr = Global.grow(ptr, empty, full).unwrap();
+            ptr.as_ptr().write_bytes(7, 32);
+            let ptr = Global.shrink(ptr, full, empty).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % 16, 0);
+            Global.deallocate(ptr, empty);
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use crate::sync::{self, AtomicUsize, Ordering};
+use std::alloc::{self, Layout};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ptr::{self, NonNull};
+
+// Past this many references we abort, like std does, instead of letting the count wrap around.
+// Leaves room for the threads racing between the increment and the check.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+struct ArcInner<T> {
+    // Number of Arc's pointing at this allocation, the value is dropped when it reaches 0.
+    strong: AtomicUsize,
+    // Number of Weak's pointing at this allocation, plus one shared by all the Arc's,
+    // the allocation is freed when it reaches 0.
+    // usize::MAX while `Arc::get_mut` checks for uniqueness, so no Weak can be made in between.
+    weak: AtomicUsize,
+    value: T,
+}
+// Atomically Reference Counted Pointer, the same bookkeeper as Rc but the counts are atomics.
+// +Shared access to values
+// +Threadable
+// -Size Increase
+// -Runtime Cost, more than Rc since every count change is an atomic operation
+pub struct Arc<T> {
+    inner: NonNull<ArcInner<T>>,
+    _marker: PhantomData<ArcInner<T>>,
+}
+
+// An Arc hands out &T to every thread holding a clone, so T must be Sync.
+// The last Arc to go away drops T on whichever thread that is, so T must be Send.
+unsafe impl<T: Send + Sync> Send for Arc<T> {}
+unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+
+impl<T> Arc<T> {
+    pub fn new(v: T) -> Self {
+        let inner = Box::new(ArcInner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            value: v,
+        });
+        Arc {
+            // SAFETY: Box does not give us a null pointer.
+            inner: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) },
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        let weak = &this.inner().weak;
+        let mut c = weak.load(Ordering::Relaxed);
+        loop {
+            if c == usize::MAX {
+                // `get_mut` has the weak count locked, wait for it to put it back.
+                sync::spin_loop();
+                c = weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if c > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // Acquire pairs with the Release store in `is_unique`.
+            match weak.compare_exchange_weak(c, c + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => {
+                    return Weak {
+                        inner: Some(this.inner),
+                    }
+                }
+                Err(old) => c = old,
+            }
+        }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Acquire)
+    }
+
+    pub fn weak_count(this: &Self) -> usize {
+        // The Arc's share one weak reference between them, which isn't a Weak.
+        let cnt = this.inner().weak.load(Ordering::Acquire);
+        // Another thread can be in `get_mut` on its own clone, with the count locked.
+        // Same as std, that means there were no Weak's, or it couldn't have locked it.
+        if cnt == usize::MAX {
+            0
+        } else {
+            cnt - 1
+        }
+    }
+
+    // Mutable access to the value, only if no other Arc or Weak points to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if this.is_unique() {
+            // SAFETY: we are the only Arc and there are no Weak's that could upgrade,
+            // and we hold a &mut to that Arc, so nobody else can get to the value.
+            Some(unsafe { &mut (*this.inner.as_ptr()).value })
+        } else {
+            None
+        }
+    }
+
+    // Clone on write, clones the value into a new allocation if it is shared.
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if !this.is_unique() {
+            *this = Arc::new((**this).clone());
+        }
+        // SAFETY: either it was unique already, or we have just made a new allocation
+        // which nobody else has seen yet.
+        unsafe { &mut (*this.inner.as_ptr()).value }
+    }
+
+    // Takes the value out if this is the only Arc, otherwise gives the Arc back.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if this
+            .inner()
+            .strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        // Same as in Drop, see every access to the value the other Arc's made before they went away.
+        sync::fence(Ordering::Acquire);
+        let this = ManuallyDrop::new(this);
+        // SAFETY: strong is 0 so no Weak can upgrade, and we were the last Arc,
+        // so nobody else can read the value. It's moved out and never dropped in place.
+        let value = unsafe { ptr::read(&this.inner().value) };
+        // SAFETY: the allocation is still there, we hold the weak reference shared by the Arc's.
+        unsafe { release_weak(this.inner) };
+        Ok(value)
+    }
+
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.inner == other.inner
+    }
+
+    fn is_unique(&mut self) -> bool {
+        // Lock the weak count so no Weak can be made while we look at the strong count.
+        // Acquire so we see any Weak that was dropped before.
+        let inner = self.inner();
+        if inner
+            .weak
+            .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            // Acquire pairs with the Release decrement in Drop, so the writes of other Arc's
+            // that are now gone happen before we hand out a &mut.
+            let unique = inner.strong.load(Ordering::Acquire) == 1;
+            inner.weak.store(1, Ordering::Release);
+            unique
+        } else {
+            false
+        }
+    }
+
+    fn inner(&self) -> &ArcInner<T> {
+        // SAFETY: the allocation lives at least as long as any Arc pointing to it.
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl<T> std::ops::Deref for Arc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFTEY: the value is only dropped when the last Arc goes away,
+        // we have an Arc, therefore deref is fine.
+        &self.inner().value
+    }
+}
+
+impl<T> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        // Relaxed is enough, a new reference can only be made from an existing one,
+        // so nothing else has to be ordered with it.
+        let old = self.inner().strong.fetch_add(1, Ordering::Relaxed);
+        if old > MAX_REFCOUNT {
+            std::process::abort();
+        }
+        Arc {
+            inner: self.inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for Arc<T> {
+    fn drop(&mut self) {
+        // Release so that everything this thread did with the value happens before
+        // whichever thread ends up dropping it.
+        if self.inner().strong.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // We were the last Arc, Acquire pairs with the Release of every other Arc that went away.
+        sync::fence(Ordering::Acquire);
+        // SAFETY: we are the _only_ Arc left, and we are being dropped.
+        // Weak's can't upgrade anymore since strong is 0, so they never see the value again.
+        unsafe { ptr::drop_in_place(ptr::addr_of_mut!((*self.inner.as_ptr()).value)) };
+        // SAFETY: the allocation itself has not been freed yet, we still hold that weak reference.
+        unsafe { release_weak(self.inner) };
+    }
+}
+
+// A non-owning pointer to an Arc allocation, same as rc::Weak but can be sent across threads.
+pub struct Weak<T> {
+    // None for a Weak made with `Weak::new`, which never had an allocation.
+    inner: Option<NonNull<ArcInner<T>>>,
+}
+
+// A Weak can be upgraded into an Arc on another thread, so it needs the same bounds.
+unsafe impl<T: Send + Sync> Send for Weak<T> {}
+unsafe impl<T: Send + Sync> Sync for Weak<T> {}
+
+impl<T> Weak<T> {
+    pub fn new() -> Self {
+        Weak { inner: None }
+    }
+
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        let ptr = self.inner?;
+        let strong = self.inner()?.strong;
+        // Can't use fetch_add, the value might be dropped between a check and the increment.
+        let mut c = strong.load(Ordering::Relaxed);
+        loop {
+            if c == 0 {
+                // The value has already been dropped.
+                return None;
+            }
+            if c > MAX_REFCOUNT {
+                std::process::abort();
+            }
+            // Acquire so the new Arc sees the value as the other Arc's left it.
+            match strong.compare_exchange_weak(c, c + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => {
+                    return Some(Arc {
+                        inner: ptr,
+                        _marker: PhantomData,
+                    })
+                }
+                Err(old) => c = old,
+            }
+        }
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner()
+            .map_or(0, |inner| inner.strong.load(Ordering::Acquire))
+    }
+
+    pub fn weak_count(&self) -> usize {
+        let inner = match self.inner() {
+            Some(inner) => inner,
+            None => return 0,
+        };
+        let weak = inner.weak.load(Ordering::Acquire);
+        if inner.strong.load(Ordering::Acquire) == 0 {
+            // Same as std, there is nothing to count once the value is gone.
+            0
+        } else {
+            weak - 1
+        }
+    }
+
+    fn inner(&self) -> Option<WeakInner<'_>> {
+        // SAFETY: the allocation is only freed once the last Weak is gone, and we are a Weak.
+        // Only references to the counts are made, since the value may already have been dropped.
+        self.inner.map(|inner| unsafe {
+            WeakInner {
+                strong: &*ptr::addr_of!((*inner.as_ptr()).strong),
+                weak: &*ptr::addr_of!((*inner.as_ptr()).weak),
+            }
+        })
+    }
+}
+
+// The counts of an ArcInner, which stay valid for as long as the allocation does.
+struct WeakInner<'a> {
+    strong: &'a AtomicUsize,
+    weak: &'a AtomicUsize,
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if let Some(inner) = self.inner() {
+            // We are a Weak so the count can't be locked by `get_mut`, which needs it to be 1.
+            let old = inner.weak.fetch_add(1, Ordering::Relaxed);
+            if old > MAX_REFCOUNT {
+                std::process::abort();
+            }
+        }
+        Weak { inner: self.inner }
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner {
+            // SAFETY: we hold a weak reference, so the allocation is still around.
+            unsafe { release_weak(inner) };
+        }
+    }
+}
+
+// Gives up one weak reference and frees the allocation if it was the last one.
+// SAFETY: the caller must own a weak reference to a live allocation made by `Arc::new`,
+// and the value must already have been dropped if this is the last weak reference.
+unsafe fn release_weak<T>(inner: NonNull<ArcInner<T>>) {
+    let weak = &*ptr::addr_of!((*inner.as_ptr()).weak);
+    // Release/Acquire for the same reason as the strong count in Arc's Drop.
+    if weak.fetch_sub(1, Ordering::Release) == 1 {
+        sync::fence(Ordering::Acquire);
+        // Don't go through Box::from_raw, that would drop the value a second time.
+        alloc::dealloc(inner.as_ptr() as *mut u8, Layout::new::<ArcInner<T>>());
+    }
+}
+
+/// ```compile_fail
+/// use pointers::{arc::Arc, refcell::RefCell};
+/// let x = Arc::new(RefCell::new(42));
+/// std::thread::spawn(move || drop(x));
+/// ```
+#[allow(dead_code)]
+struct CompileFailTest;
+
+// The loom models below replace these tests under `--cfg loom`, since loom's atomics
+// can only be used inside a model.
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{Arc, Weak, MAX_REFCOUNT};
+    use crate::test_util::{assert_aborts, in_abort_child, DropCounter};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Arc<String>>();
+        assert_send_sync::<Weak<String>>();
+    }
+
+    #[test]
+    fn across_threads() {
+        let config = Arc::new(vec![1, 2, 3]);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let config = Arc::clone(&config);
+                std::thread::spawn(move || config.iter().sum::<i32>())
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 6);
+        }
+        assert_eq!(Arc::strong_count(&config), 1);
+    }
+
+    #[test]
+    fn dropped_once_across_threads() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        let a = Arc::new(DropCounter(&DROPPED));
+        let w = Arc::downgrade(&a);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let a = Arc::clone(&a);
+                let w = w.clone();
+                std::thread::spawn(move || {
+                    let _ = w.upgrade();
+                    drop(a);
+                })
+            })
+            .collect();
+        drop(a);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.strong_count(), 0);
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut a = Arc::new(1);
+        *Arc::get_mut(&mut a).unwrap() += 1;
+        let b = Arc::clone(&a);
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(b);
+        let w = Arc::downgrade(&a);
+        // A Weak could upgrade and see the value change under it.
+        assert!(Arc::get_mut(&mut a).is_none());
+        drop(w);
+        assert_eq!(Arc::get_mut(&mut a), Some(&mut 2));
+    }
+
+    #[test]
+    fn make_mut() {
+        let mut a = Arc::new(vec![1]);
+        Arc::make_mut(&mut a).push(2);
+        let b = Arc::clone(&a);
+        Arc::make_mut(&mut a).push(3);
+        assert_eq!(*a, [1, 2, 3]);
+        assert_eq!(*b, [1, 2]);
+        assert!(!Arc::ptr_eq(&a, &b));
+
+        let w = Arc::downgrade(&b);
+        let mut b = b;
+        Arc::make_mut(&mut b).push(4);
+        // The old allocation only had Weak's left, so its value is gone.
+        assert!(w.upgrade().is_none());
+        assert_eq!(*b, [1, 2, 4]);
+    }
+
+    #[test]
+    fn try_unwrap() {
+        let a = Arc::new(String::from("hello"));
+        let b = Arc::clone(&a);
+        let a = Arc::try_unwrap(a).unwrap_err();
+        drop(b);
+        let w = Arc::downgrade(&a);
+        assert_eq!(Arc::try_unwrap(a).ok().unwrap(), "hello");
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn try_unwrap_drops_once() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        let a = Arc::new(DropCounter(&DROPPED));
+        let w = Arc::downgrade(&a);
+        let value = Arc::try_unwrap(a).ok().unwrap();
+        drop(w);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+        drop(value);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Miri can't spawn the child process.
+    fn clone_overflow_aborts() {
+        assert_aborts("arc::tests::clone_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by clone_overflow_aborts"]
+    fn clone_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Arc::new(());
+        // Same as having forgotten that many clones, without the wait.
+        a.inner().strong.store(MAX_REFCOUNT + 1, Ordering::Relaxed);
+        let _ = Arc::clone(&a);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // Miri can't spawn the child process.
+    fn downgrade_overflow_aborts() {
+        assert_aborts("arc::tests::downgrade_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by downgrade_overflow_aborts"]
+    fn downgrade_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Arc::new(());
+        a.inner().weak.store(MAX_REFCOUNT + 1, Ordering::Relaxed);
+        let _ = Arc::downgrade(&a);
+    }
+
+    #[test]
+    fn counts() {
+        let a = Arc::new(());
+        let w = Arc::downgrade(&a);
+        let _b = a.clone();
+        assert_eq!(Arc::strong_count(&a), 2);
+        assert_eq!(Arc::weak_count(&a), 1);
+        assert_eq!(w.weak_count(), 1);
+        assert_eq!(Weak::<()>::new().strong_count(), 0);
+    }
+}
+
+// Run with `RUSTFLAGS="--cfg loom" cargo test -p pointers --release loom`.
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::Arc;
+    use crate::test_util::DropCounter;
+    use loom::sync::atomic::{AtomicUsize, Ordering};
+    use loom::thread;
+
+    #[test]
+    fn clone_and_drop() {
+        loom::model(|| {
+            let dropped = loom::sync::Arc::new(AtomicUsize::new(0));
+            let a = Arc::new(DropCounter(dropped.clone()));
+            let b = Arc::clone(&a);
+            let t = thread::spawn(move || drop(b));
+            drop(a);
+            t.join().unwrap();
+            assert_eq!(dropped.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn upgrade_races_last_drop() {
+        loom::model(|| {
+            let dropped = loom::sync::Arc::new(AtomicUsize::new(0));
+            let a = Arc::new(DropCounter(dropped.clone()));
+            let w = Arc::downgrade(&a);
+            let t = thread::spawn(move || {
+                // Either the value is gone, or we keep it alive until we're done with it.
+                if let Some(a) = w.upgrade() {
+                    assert_eq!(a.0.load(Ordering::Relaxed), 0);
+                }
+            });
+            drop(a);
+            t.join().unwrap();
+            assert_eq!(dropped.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn get_mut_races_downgrade() {
+        loom::model(|| {
+            let mut a = Arc::new(0);
+            let b = Arc::clone(&a);
+            let t = thread::spawn(move || {
+                let w = Arc::downgrade(&b);
+                drop(b);
+                w.upgrade().map(|v| *v)
+            });
+            if let Some(v) = Arc::get_mut(&mut a) {
+                *v = 1;
+            }
+            // get_mut only succeeds once the other thread has given up both its Arc and its
+            // Weak, so it can never see the write.
+            let seen = t.join().unwrap();
+            assert_ne!(seen, Some(1));
+        });
+    }
+
+    #[test]
+    fn weak_count_races_get_mut() {
+        loom::model(|| {
+            let a = Arc::new(0);
+            let mut b = Arc::clone(&a);
+            // Fails since `a` is still around, but locks the weak count while it looks.
+            let t = thread::spawn(move || assert!(Arc::get_mut(&mut b).is_none()));
+            assert_eq!(Arc::weak_count(&a), 0);
+            t.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn try_unwrap_races_drop() {
+        loom::model(|| {
+            let dropped = loom::sync::Arc::new(AtomicUsize::new(0));
+            let a = Arc::new(DropCounter(dropped.clone()));
+            let b = Arc::clone(&a);
+            let t = thread::spawn(move || drop(b));
+            let value = Arc::try_unwrap(a);
+            t.join().unwrap();
+            drop(value);
+            assert_eq!(dropped.load(Ordering::Relaxed), 1);
+        });
+    }
+}
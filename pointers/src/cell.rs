@@ -3,12 +3,13 @@ use std::cell::UnsafeCell;
 // +Interior Mutability
 // -Size Increase
 // -Performance
-pub struct Cell<T> {
+// repr(transparent) so a Cell<T> has the same layout as a T, see `from_mut`.
+#[repr(transparent)]
+pub struct Cell<T: ?Sized> {
     value: UnsafeCell<T>,
 }
 
 // impl<T> !Sync for Cell<T> {} //  Implied by UnsafeCell
-unsafe impl<T> Sync for Cell<T> {}
 
 impl<T> Cell<T> {
     pub fn new(value: T) -> Self {
@@ -18,11 +19,9 @@ impl<T> Cell<T> {
     }
 
     pub fn set(&self, value: T) {
-        unsafe {
-            // SAFETY: None of the references are being invalidated, becuase we never give any out
-            // SAFETY: No one can concurrently mutate self.value because !Sync
-            *self.value.get() = value;
-        }
+        // The old value is dropped after we're done touching the cell,
+        // so its Drop can't see the cell half way through a write.
+        drop(self.replace(value));
     }
 
     pub fn get(&self) -> T
@@ -32,24 +31,226 @@ impl<T> Cell<T> {
         // SAFETY: No one modify's this value, since only this thread can mutate (because !Synce), and it is executing this function instead.
         unsafe { *self.value.get() }
     }
+
+    pub fn replace(&self, value: T) -> T {
+        // SAFETY: None of the references are being invalidated, becuase we never give any out
+        // SAFETY: No one can concurrently mutate self.value because !Sync
+        unsafe { std::mem::replace(&mut *self.value.get(), value) }
+    }
+
+    pub fn take(&self) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default())
+    }
+
+    pub fn swap(&self, other: &Self) {
+        if std::ptr::eq(self, other) {
+            return;
+        }
+        // SAFETY: Both cells are on this thread (because !Sync) and we never give out references
+        // to their contents, and they are not the same cell, so the two pointers don't overlap.
+        unsafe { std::ptr::swap(self.value.get(), other.value.get()) }
+    }
+
+    pub fn update(&self, f: impl FnOnce(T) -> T)
+    where
+        T: Copy,
+    {
+        self.set(f(self.get()));
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Cell<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        // &mut self means nobody else can be looking at the cell, no need for unsafe.
+        self.value.get_mut()
+    }
+
+    // Lends out a value we have unique access to as a Cell, e.g. to mutate elements of a
+    // slice through shared references. The value is back to normal once the borrow ends.
+    pub fn from_mut(t: &mut T) -> &Cell<T> {
+        // SAFETY: Cell<T> is repr(transparent) over UnsafeCell<T>, which has the same layout as T.
+        // The &mut T is given up for as long as the &Cell<T> lives, so the Cell is the only way
+        // to reach the value, and it's !Sync so it can only be used from this thread.
+        unsafe { &*(t as *mut T as *const Cell<T>) }
+    }
+}
+
+impl<T> Cell<[T]> {
+    // A Cell around a slice is a slice of Cells, each element can be set on its own.
+    pub fn as_slice_of_cells(&self) -> &[Cell<T>] {
+        // SAFETY: Cell<[T]> has the same layout as [T], and Cell<T> as T, so [Cell<T>] has the
+        // same layout as [T], and the cast keeps the length. Setting one element can't
+        // invalidate a reference to any other, since Cell never hands those out.
+        unsafe { &*(self as *const Cell<[T]> as *const [Cell<T>]) }
+    }
 }
 
+// The same impls as std's Cell, the ones that read the value need T: Copy since `get` does.
+impl<T: Copy + std::fmt::Debug> std::fmt::Debug for Cell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cell").field("value", &self.get()).finish()
+    }
+}
+
+impl<T: Copy> Clone for Cell<T> {
+    fn clone(&self) -> Self {
+        Cell::new(self.get())
+    }
+}
+
+impl<T: Default> Default for Cell<T> {
+    fn default() -> Self {
+        Cell::new(T::default())
+    }
+}
+
+impl<T> From<T> for Cell<T> {
+    fn from(value: T) -> Self {
+        Cell::new(value)
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq for Cell<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl<T: Copy + Eq> Eq for Cell<T> {}
+
+impl<T: Copy + PartialOrd> PartialOrd for Cell<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.get().partial_cmp(&other.get())
+    }
+}
+
+impl<T: Copy + Ord> Ord for Cell<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.get().cmp(&other.get())
+    }
+}
+
+/// ```compile_fail
+/// use pointers::cell::Cell;
+/// use std::sync::Arc;
+/// let x = Arc::new(Cell::new(42));
+/// let x1 = Arc::clone(&x);
+/// std::thread::spawn(move || {
+///     x1.set(43);
+/// });
+/// let x2 = Arc::clone(&x);
+/// std::thread::spawn(move || {
+///     x2.set(44);
+/// });
+/// ```
+#[allow(dead_code)]
+struct CompileFailTest;
+
 #[cfg(test)]
 mod test {
     use super::Cell;
+
     #[test]
-    fn bad() {
-        use std::sync::Arc;
-        let x = Arc::new(Cell::new(42));
-        let x1 = Arc::clone(&x);
-        std::thread::spawn(move || {
-            x1.set(43);
-        });
-        let x2 = Arc::clone(&x);
-        std::thread::spawn(move || {
-            x2.set(44);
-        });
+    fn set_get() {
+        let x = Cell::new(42);
+        let y = &x;
+        assert_eq!(x.get(), 42);
+        y.set(43);
+        assert_eq!(x.get(), 43);
     }
+
+    #[test]
+    fn replace_take() {
+        let x = Cell::new(String::from("Hello"));
+        assert_eq!(x.replace(String::from("World")), "Hello");
+        assert_eq!(x.take(), "World");
+        assert_eq!(x.into_inner(), "");
+    }
+
+    #[test]
+    fn swap() {
+        let x = Cell::new(vec![1]);
+        let y = Cell::new(vec![2, 3]);
+        x.swap(&y);
+        x.swap(&x);
+        assert_eq!(x.into_inner(), [2, 3]);
+        assert_eq!(y.into_inner(), [1]);
+    }
+
+    #[test]
+    fn update_get_mut() {
+        let mut x = Cell::new(1);
+        x.update(|v| v + 1);
+        *x.get_mut() *= 10;
+        assert_eq!(x.get(), 20);
+    }
+
+    #[test]
+    fn set_drops_old_value() {
+        let old = std::rc::Rc::new(());
+        let x = Cell::new(std::rc::Rc::clone(&old));
+        assert_eq!(std::rc::Rc::strong_count(&old), 2);
+        x.set(std::rc::Rc::new(()));
+        assert_eq!(std::rc::Rc::strong_count(&old), 1);
+    }
+
+    #[test]
+    fn traits() {
+        let x: Cell<i32> = Cell::default();
+        let y = Cell::from(1);
+        assert_eq!(format!("{:?}", y), "Cell { value: 1 }");
+        assert!(x < y);
+        assert_eq!(x.clone(), Cell::new(0));
+        assert_eq!(std::cmp::max(x, y).get(), 1);
+    }
+
+    #[test]
+    fn from_mut() {
+        let mut x = 1;
+        let cell = Cell::from_mut(&mut x);
+        let alias = cell;
+        alias.set(2);
+        assert_eq!(cell.get(), 2);
+        assert_eq!(x, 2);
+    }
+
+    #[test]
+    fn slice_of_cells() {
+        let mut v = vec![1, 2, 3];
+        let cells = Cell::from_mut(&mut v[..]).as_slice_of_cells();
+        assert_eq!(cells.len(), 3);
+        // Two shared references into the same slice, both used for writing.
+        let (first, last) = (&cells[0], &cells[2]);
+        first.set(last.get() * 10);
+        last.swap(first);
+        assert_eq!(v, [3, 2, 30]);
+    }
+
+    // The kind of in-place graph update from_mut is for: every node pushes its value to its
+    // neighbours while we iterate over the same slice.
+    #[test]
+    fn relax_neighbours() {
+        let edges: [&[usize]; 4] = [&[1, 2], &[3], &[3], &[]];
+        let mut dist = [0, u32::MAX, u32::MAX, u32::MAX];
+        let cells = Cell::from_mut(&mut dist[..]).as_slice_of_cells();
+        for (node, d) in cells.iter().enumerate() {
+            for &next in edges[node] {
+                let through = d.get().saturating_add(1);
+                if through < cells[next].get() {
+                    cells[next].set(through);
+                }
+            }
+        }
+        assert_eq!(dist, [0, 1, 1, 2]);
+    }
+
     // #[test]
     // fn bad2() {
     //     let x = Cell::new(String::from("Hello")); // Allocated Hello
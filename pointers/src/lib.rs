@@ -1,3 +1,11 @@
+pub mod allocator;
+pub mod arc;
 pub mod cell;
+pub mod list;
+pub mod once;
 pub mod rc;
 pub mod refcell;
+pub mod rwcell;
+mod sync;
+#[cfg(test)]
+mod test_util;
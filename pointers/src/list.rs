@@ -0,0 +1,449 @@
+use crate::rc::{Rc, Weak};
+use crate::refcell::RefCell;
+
+// A node owns the next one, and only points weakly back at the previous one,
+// so the list doesn't form Rc cycles and the nodes are freed once the list lets go.
+struct Node<T> {
+    value: T,
+    next: RefCell<Option<Rc<Node<T>>>>,
+    prev: RefCell<Weak<Node<T>>>,
+}
+
+impl<T> Node<T> {
+    fn new(value: T) -> Rc<Self> {
+        Rc::new(Node {
+            value,
+            next: RefCell::new(None),
+            prev: RefCell::new(Weak::new()),
+        })
+    }
+
+    // Takes the value back out of a node that has been unlinked.
+    fn into_value(node: Rc<Self>) -> T {
+        // The list's links were the only strong references, the Weak's left don't count.
+        Rc::into_inner(node)
+            .unwrap_or_else(|| unreachable!("an unlinked node has no other Rc's"))
+            .value
+    }
+}
+
+// Doubly linked list built out of Rc, Weak and RefCell.
+// +O(1) push and pop at both ends, and O(1) removal through a cursor
+// +No unsafe for the links, the borrow checks and refcounts keep them straight
+// -Every node is its own allocation, with two counts and two borrow flags on top of the value
+// -Runtime Cost, every step through the list borrows a RefCell
+pub struct LinkedList<T> {
+    head: Option<Rc<Node<T>>>,
+    // The last node is also owned by its previous node (or head), tail is a second Rc to it.
+    tail: Option<Rc<Node<T>>>,
+    len: usize,
+}
+
+impl<T> LinkedList<T> {
+    pub fn new() -> Self {
+        LinkedList {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let node = Node::new(value);
+        match self.head.take() {
+            Some(old) => {
+                *old.prev.borrow_mut() = Rc::downgrade(&node);
+                *node.next.borrow_mut() = Some(old);
+            }
+            None => self.tail = Some(Rc::clone(&node)),
+        }
+        self.head = Some(node);
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        let node = Node::new(value);
+        match self.tail.take() {
+            Some(old) => {
+                *node.prev.borrow_mut() = Rc::downgrade(&old);
+                *old.next.borrow_mut() = Some(Rc::clone(&node));
+            }
+            None => self.head = Some(Rc::clone(&node)),
+        }
+        self.tail = Some(node);
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        let old = self.head.take()?;
+        let next = old.next.borrow_mut().take();
+        match next {
+            Some(next) => {
+                *next.prev.borrow_mut() = Weak::new();
+                self.head = Some(next);
+            }
+            None => self.tail = None,
+        }
+        self.len -= 1;
+        Some(Node::into_value(old))
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        let old = self.tail.take()?;
+        let prev = old.prev.borrow().upgrade();
+        match prev {
+            Some(prev) => {
+                // Drops the other strong reference to the old tail.
+                prev.next.borrow_mut().take();
+                self.tail = Some(prev);
+            }
+            None => self.head = None,
+        }
+        self.len -= 1;
+        Some(Node::into_value(old))
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.head.as_deref().map(|node| &node.value)
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        self.tail.as_deref().map(|node| &node.value)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            front: self.head.as_deref(),
+            back: self.tail.as_deref(),
+            len: self.len,
+        }
+    }
+
+    // A cursor starting at the first element, or at the "ghost" position if the list is empty.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.head.clone();
+        CursorMut {
+            list: self,
+            current,
+        }
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.tail.clone();
+        CursorMut {
+            list: self,
+            current,
+        }
+    }
+}
+
+impl<T> Default for LinkedList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for LinkedList<T> {
+    fn drop(&mut self) {
+        // Dropping head would drop its next, which drops its next, and so on, one stack frame
+        // per node. Unlink them one at a time instead, so a long list can't overflow the stack.
+        self.tail = None;
+        let mut head = self.head.take();
+        while let Some(node) = head {
+            head = node.next.borrow_mut().take();
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for LinkedList<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> FromIterator<T> for LinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = LinkedList::new();
+        for value in iter {
+            list.push_back(value);
+        }
+        list
+    }
+}
+
+impl<'a, T> IntoIterator for &'a LinkedList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for LinkedList<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self)
+    }
+}
+
+pub struct Iter<'a, T> {
+    front: Option<&'a Node<T>>,
+    back: Option<&'a Node<T>>,
+    // How many elements are left between front and back, so the two ends stop when they meet.
+    len: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = self.front?;
+        // SAFETY: the list is borrowed for 'a, so none of its links change and every node stays
+        // owned by the list until then. That's what keeps the node alive, not the RefCell guard.
+        self.front = node
+            .next
+            .borrow()
+            .as_deref()
+            .map(|next| unsafe { &*(next as *const Node<T>) });
+        self.len -= 1;
+        Some(&node.value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = self.back?;
+        // SAFETY: same as `next`, the upgraded Rc is dropped right away,
+        // but the list still owns the node for 'a.
+        self.back = node
+            .prev
+            .borrow()
+            .upgrade()
+            .map(|prev| unsafe { &*(&*prev as *const Node<T>) });
+        self.len -= 1;
+        Some(&node.value)
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+pub struct IntoIter<T>(LinkedList<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop_front()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.0.len, Some(self.0.len))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+// Points at an element of the list, or at the "ghost" position past the last element
+// (and before the first), same as std's cursors. Moving past either end goes to the ghost.
+pub struct CursorMut<'a, T> {
+    list: &'a mut LinkedList<T>,
+    // None at the ghost position.
+    current: Option<Rc<Node<T>>>,
+}
+
+impl<T> CursorMut<'_, T> {
+    pub fn current(&self) -> Option<&T> {
+        self.current.as_deref().map(|node| &node.value)
+    }
+
+    pub fn move_next(&mut self) {
+        self.current = match self.current.take() {
+            Some(node) => node.next.borrow().clone(),
+            None => self.list.head.clone(),
+        };
+    }
+
+    pub fn move_prev(&mut self) {
+        self.current = match self.current.take() {
+            Some(node) => node.prev.borrow().upgrade(),
+            None => self.list.tail.clone(),
+        };
+    }
+
+    // Unlinks the current element and hands it back, the cursor moves on to the next one.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node = self.current.take()?;
+        let prev = node.prev.borrow().upgrade();
+        let next = node.next.borrow_mut().take();
+        // Each of these overwrites one of the two strong references to the node.
+        match &prev {
+            Some(prev) => *prev.next.borrow_mut() = next.clone(),
+            None => self.list.head = next.clone(),
+        }
+        match &next {
+            Some(next) => {
+                *next.prev.borrow_mut() = prev.as_ref().map(Rc::downgrade).unwrap_or_default()
+            }
+            None => self.list.tail = prev,
+        }
+        self.current = next;
+        self.list.len -= 1;
+        Some(Node::into_value(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkedList;
+    use crate::test_util::DropCounter;
+
+    #[test]
+    fn push_pop() {
+        let mut list = LinkedList::new();
+        assert_eq!(list.pop_front(), None);
+        assert_eq!(list.pop_back(), None);
+        list.push_back(2);
+        list.push_front(1);
+        list.push_back(3);
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.front(), Some(&1));
+        assert_eq!(list.back(), Some(&3));
+        assert_eq!(list.pop_back(), Some(3));
+        assert_eq!(list.pop_front(), Some(1));
+        assert_eq!(list.pop_front(), Some(2));
+        assert!(list.is_empty());
+        assert_eq!(list.front(), None);
+        assert_eq!(list.back(), None);
+        list.push_front(4);
+        assert_eq!(list.pop_back(), Some(4));
+        assert_eq!(list.pop_front(), None);
+    }
+
+    #[test]
+    fn iter() {
+        let list: LinkedList<_> = (1..=5).collect();
+        assert_eq!(list.iter().len(), 5);
+        assert!(list.iter().copied().eq(1..=5));
+        assert!(list.iter().rev().copied().eq((1..=5).rev()));
+        let mut it = list.iter();
+        assert_eq!(it.next(), Some(&1));
+        assert_eq!(it.next_back(), Some(&5));
+        assert_eq!(it.next_back(), Some(&4));
+        assert_eq!(it.next(), Some(&2));
+        assert_eq!(it.next(), Some(&3));
+        assert_eq!(it.next(), None);
+        assert_eq!(it.next_back(), None);
+        assert_eq!(format!("{:?}", list), "[1, 2, 3, 4, 5]");
+        let mut owned = list.into_iter();
+        assert_eq!(owned.next_back(), Some(5));
+        assert_eq!(owned.collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn cursor_remove() {
+        let mut list: LinkedList<_> = (1..=5).collect();
+        let mut cursor = list.cursor_front_mut();
+        // Removing the head.
+        assert_eq!(cursor.remove_current(), Some(1));
+        assert_eq!(cursor.current(), Some(&2));
+        cursor.move_next();
+        // Removing from the middle.
+        assert_eq!(cursor.remove_current(), Some(3));
+        assert_eq!(cursor.current(), Some(&4));
+        cursor.move_next();
+        // Removing the tail lands on the ghost.
+        assert_eq!(cursor.remove_current(), Some(5));
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.remove_current(), None);
+        cursor.move_prev();
+        assert_eq!(cursor.current(), Some(&4));
+        cursor.move_prev();
+        assert_eq!(cursor.current(), Some(&2));
+        cursor.move_prev();
+        assert_eq!(cursor.current(), None);
+        cursor.move_next();
+        assert_eq!(cursor.current(), Some(&2));
+        assert_eq!(list.len(), 2);
+        assert!(list.iter().copied().eq([2, 4]));
+        assert!(list.iter().rev().copied().eq([4, 2]));
+        list.push_back(6);
+        list.push_front(0);
+        assert!(list.iter().copied().eq([0, 2, 4, 6]));
+    }
+
+    #[test]
+    fn cursor_remove_all() {
+        let mut list: LinkedList<_> = (1..=3).collect();
+        let mut cursor = list.cursor_back_mut();
+        assert_eq!(cursor.remove_current(), Some(3));
+        cursor.move_prev();
+        assert_eq!(cursor.remove_current(), Some(2));
+        assert_eq!(cursor.current(), None);
+        cursor.move_next();
+        assert_eq!(cursor.remove_current(), Some(1));
+        assert!(list.is_empty());
+        assert_eq!(list.front(), None);
+        assert_eq!(list.back(), None);
+    }
+
+    // Moves an element to the front on every hit, the way an LRU cache uses the list.
+    #[test]
+    fn lru() {
+        let mut list: LinkedList<_> = ["a", "b", "c"].into_iter().collect();
+        for hit in ["c", "a", "c"] {
+            let mut cursor = list.cursor_front_mut();
+            while cursor.current() != Some(&hit) {
+                cursor.move_next();
+            }
+            let key = cursor.remove_current().unwrap();
+            list.push_front(key);
+        }
+        assert!(list.iter().copied().eq(["c", "a", "b"]));
+        // The least recently used is at the back.
+        assert_eq!(list.pop_back(), Some("b"));
+    }
+
+    #[test]
+    fn drops_each_value_once() {
+        let dropped = std::cell::Cell::new(0);
+        let mut list: LinkedList<_> = (0..10).map(|_| DropCounter(&dropped)).collect();
+        drop(list.pop_front());
+        drop(list.pop_back());
+        list.cursor_front_mut().remove_current();
+        assert_eq!(dropped.get(), 3);
+        drop(list);
+        assert_eq!(dropped.get(), 10);
+    }
+
+    #[test]
+    fn long_list_drop() {
+        // Big enough to overflow the stack if each node's drop recursed into the next.
+        let n = if cfg!(miri) { 1_000 } else { 1_000_000 };
+        let list: LinkedList<_> = (0..n).collect();
+        assert_eq!(list.len(), n);
+        drop(list);
+    }
+}
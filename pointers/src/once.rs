@@ -0,0 +1,227 @@
+use std::cell::UnsafeCell;
+
+use crate::cell::Cell;
+// A cell that is written at most once, after which shared references to the value can be handed out.
+// +Interior Mutability without any borrow tracking once written
+// +No runtime cost to read after the first write
+// -Can't be changed again without &mut
+// -Not Threadable
+pub struct OnceCell<T> {
+    value: UnsafeCell<Option<T>>,
+    // Set while `get_or_try_init` is running the init function, to catch it re-entering.
+    initializing: Cell<bool>,
+}
+
+// impl<T> !Sync for OnceCell<T> {} //  Implied by UnsafeCell
+impl<T> OnceCell<T> {
+    pub fn new() -> Self {
+        OnceCell {
+            value: UnsafeCell::new(None),
+            initializing: Cell::new(false),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        // SAFETY: the Option is only written while it's None, and we only hand out references
+        // into it once it's Some, so no reference we give out is ever invalidated.
+        unsafe { &*self.value.get() }.as_ref()
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.value.get_mut().as_mut()
+    }
+
+    // Gives the value back if the cell was already written, or is being written right now.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        if self.get().is_some() || self.initializing.get() {
+            return Err(value);
+        }
+        // SAFETY: the cell is empty, so nobody holds a reference into it,
+        // and we're not inside an init function that could be looking at it.
+        unsafe { *self.value.get() = Some(value) };
+        Ok(())
+    }
+
+    // Panics if `f` calls back into `get_or_init` on the same cell, since the value
+    // it would have to return doesn't exist yet.
+    #[track_caller]
+    pub fn get_or_init<F>(&self, f: F) -> &T
+    where
+        F: FnOnce() -> T,
+    {
+        match self.get_or_try_init(|| Ok::<T, std::convert::Infallible>(f())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    // If `f` fails the cell stays empty, and the next call tries again.
+    #[track_caller]
+    pub fn get_or_try_init<F, E>(&self, f: F) -> Result<&T, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(value) = self.get() {
+            return Ok(value);
+        }
+        if self.initializing.replace(true) {
+            panic!("reentrant init");
+        }
+        // Clears the flag again, even if `f` panics, so the cell can be retried.
+        struct Reset<'a>(&'a Cell<bool>);
+        impl Drop for Reset<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+        let reset = Reset(&self.initializing);
+        let value = f()?;
+        drop(reset);
+        // SAFETY: the cell was empty before `f`, and `f` couldn't have filled it since both
+        // `set` and `get_or_try_init` refuse to while the flag is set.
+        unsafe { *self.value.get() = Some(value) };
+        Ok(self.get().expect("just initialised"))
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        self.value.get_mut().take()
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A value that is computed by `F` the first time it's used, e.g. an expensive field.
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    // Taken out when it runs, so a Lazy whose init panicked can't run it again.
+    init: Cell<Option<F>>,
+}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub fn new(init: F) -> Self {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    // Runs the init function if it hasn't run yet, an associated function so it doesn't shadow T's methods.
+    #[track_caller]
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(f) => f(),
+            None => panic!("Lazy instance has previously been poisoned"),
+        })
+    }
+
+    pub fn get(this: &Lazy<T, F>) -> Option<&T> {
+        this.cell.get()
+    }
+}
+
+impl<T, F: FnOnce() -> T> std::ops::Deref for Lazy<T, F> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        Lazy::force(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lazy, OnceCell};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn set_get() {
+        let mut x = OnceCell::new();
+        assert!(x.get().is_none());
+        assert_eq!(x.set(1), Ok(()));
+        assert_eq!(x.set(2), Err(2));
+        assert_eq!(x.get(), Some(&1));
+        *x.get_mut().unwrap() += 1;
+        assert_eq!(x.take(), Some(2));
+        assert_eq!(x.into_inner(), None);
+    }
+
+    #[test]
+    fn get_or_init() {
+        let x = OnceCell::new();
+        let calls = std::cell::Cell::new(0);
+        let init = || {
+            calls.set(calls.get() + 1);
+            String::from("hello")
+        };
+        let a = x.get_or_init(init);
+        let b = x.get_or_init(init);
+        assert_eq!(a, "hello");
+        assert!(std::ptr::eq(a, b));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn get_or_try_init() {
+        let x = OnceCell::new();
+        assert_eq!(x.get_or_try_init(|| Err("nope")), Err("nope"));
+        assert!(x.get().is_none());
+        assert_eq!(x.get_or_try_init(|| Ok::<_, ()>(5)), Ok(&5));
+    }
+
+    #[test]
+    #[should_panic(expected = "reentrant init")]
+    fn reentrant() {
+        let x = OnceCell::new();
+        x.get_or_init(|| *x.get_or_init(|| 1) + 1);
+    }
+
+    #[test]
+    fn set_during_init() {
+        let x = OnceCell::new();
+        assert_eq!(
+            *x.get_or_init(|| {
+                assert_eq!(x.set(1), Err(1));
+                2
+            }),
+            2
+        );
+    }
+
+    #[test]
+    fn panicking_init_can_retry() {
+        let x = OnceCell::new();
+        let result = catch_unwind(AssertUnwindSafe(|| x.get_or_init(|| panic!("boom"))));
+        assert!(result.is_err());
+        assert_eq!(x.get_or_init(|| 3), &3);
+    }
+
+    #[test]
+    fn lazy() {
+        let calls = std::cell::Cell::new(0);
+        let x = Lazy::new(|| {
+            calls.set(calls.get() + 1);
+            vec![1, 2, 3]
+        });
+        assert!(Lazy::get(&x).is_none());
+        assert_eq!(x.len(), 3);
+        assert_eq!(x[0], 1);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn lazy_poisoned() {
+        let x: Lazy<i32> = Lazy::new(|| panic!("boom"));
+        assert!(catch_unwind(AssertUnwindSafe(|| *x)).is_err());
+        let e = catch_unwind(AssertUnwindSafe(|| *x)).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<&str>(),
+            Some(&"Lazy instance has previously been poisoned")
+        );
+    }
+}
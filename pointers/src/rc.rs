@@ -1,70 +1,1280 @@
+use crate::allocator::{Allocator, Global};
 use crate::cell::Cell;
+use std::alloc::{self, Layout};
 use std::marker::PhantomData;
-use std::ptr::NonNull;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::pin::Pin;
+use std::ptr::{self, NonNull};
 
-struct RcInner<T> {
+// repr(C) so an RcInner<MaybeUninit<T>> has the same layout as an RcInner<T>, see `new_cyclic`,
+// and so the value of an unsized RcInner sits right after the counts, see `data_offset`.
+// An RcInner never moves once it's allocated, the value is dropped in place and the memory is
+// only given back after that, which is what makes `Rc::pin` sound.
+#[repr(C)]
+struct RcInner<T: ?Sized> {
+    // Number of Rc's pointing at this allocation, the value is dropped when it reaches 0.
+    strong: Cell<usize>,
+    // Number of Weak's pointing at this allocation, plus one shared by all the Rc's,
+    // the allocation is freed when it reaches 0.
+    weak: Cell<usize>,
     value: T,
-    refcount: Cell<usize>,
 }
 // Reference Counted Pointer, a bookkeeper knowing who has borrowed what and when.
 // +Shared access to values
 // -Size Increase
 // -Runtime Cost
 // -Not Threadable
-pub struct Rc<T> {
+// T can be unsized, an Rc<str>, Rc<[T]> or Rc<dyn Trait> is a fat pointer to an RcInner.
+// A is where the RcInner was allocated, and where it goes back to once the last Rc or Weak is gone.
+pub struct Rc<T: ?Sized, A: Allocator = Global> {
     inner: NonNull<RcInner<T>>,
+    alloc: A,
     _marker: PhantomData<RcInner<T>>,
 }
 impl<T> Rc<T> {
     pub fn new(v: T) -> Self {
         let inner = Box::new(RcInner {
+            strong: Cell::new(1),
+            weak: Cell::new(1),
             value: v,
-            refcount: Cell::new(1),
         });
         // Rc { inner: &*inner } // Compiles but is wrong, as function return Box(new inner) is freed
         // Rc becomes invalid therefore deref becomes invalid
         Rc {
             // SAFETY: Box does not give us a null pointer.
             inner: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) }, // Now it doesn't deref it
+            // Box allocates from the global allocator, so that's where it goes back to.
+            alloc: Global,
             _marker: PhantomData,
         }
     }
+
+    // The value never moves, see RcInner, so it can be pinned right away.
+    // Self-referential values and futures can be shared this way.
+    pub fn pin(v: T) -> Pin<Rc<T>> {
+        // SAFETY: the value is never moved out of a Pin<Rc<T>>, since the Rc inside can't be
+        // reached to call `try_unwrap` or `make_mut` on it, and Rc has no DerefMut.
+        unsafe { Pin::new_unchecked(Rc::new(v)) }
+    }
+
+    // Builds a value that holds a Weak to itself, e.g. a node that knows its own Rc.
+    // The Weak can't be upgraded until `data_fn` returns, since there is no value yet.
+    pub fn new_cyclic<F>(data_fn: F) -> Self
+    where
+        F: FnOnce(&Weak<T>) -> T,
+    {
+        let uninit = Box::new(RcInner {
+            strong: Cell::new(0),
+            weak: Cell::new(1),
+            value: MaybeUninit::<T>::uninit(),
+        });
+        // SAFETY: Box does not give us a null pointer, and RcInner is repr(C) so only the type
+        // of the value field changes. strong is 0 so nothing reads the value before it's written.
+        let inner: NonNull<RcInner<T>> =
+            unsafe { NonNull::new_unchecked(Box::into_raw(uninit)).cast() };
+        // The Weak handed to data_fn is never dropped, if data_fn panics the guard gives up its
+        // weak reference instead, since dropping it would look at the value which was never written.
+        let weak = ManuallyDrop::new(Weak {
+            inner: Some(inner),
+            alloc: Global,
+        });
+        let guard = CyclicGuard(inner);
+        let value = data_fn(&weak);
+        mem::forget(guard);
+        // SAFETY: the allocation is alive since we hold a Weak, and nobody else can look at the
+        // value field while strong is 0.
+        unsafe {
+            ptr::addr_of_mut!((*inner.as_ptr()).value).write(value);
+            (*inner.as_ptr()).strong.set(1);
+        }
+        // The weak reference we made becomes the one shared by all the Rc's.
+        Rc {
+            inner,
+            alloc: Global,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// Frees the allocation of a `new_cyclic` whose data_fn panicked, the value was never written.
+struct CyclicGuard<T>(NonNull<RcInner<T>>);
+
+impl<T> Drop for CyclicGuard<T> {
+    fn drop(&mut self) {
+        // SAFETY: the guard holds the weak reference made in new_cyclic, so it's still allocated.
+        let weak = unsafe { &*ptr::addr_of!((*self.0.as_ptr()).weak) };
+        if weak.get() == 1 {
+            // T is sized, so unlike release_weak the layout doesn't need the value.
+            // SAFETY: the allocation came from a Box in Global with this layout, MaybeUninit<T>
+            // has the same layout as T, and ours was the last reference to it.
+            unsafe { Global.deallocate(self.0.cast(), Layout::new::<RcInner<T>>()) };
+        }
+        // Otherwise data_fn kept a clone of the Weak. Dropping the last one would go through
+        // release_weak, so our weak reference is never given back and the allocation leaks.
+    }
+}
+
+impl<T, A: Allocator> Rc<T, A> {
+    // Same as `new`, but the RcInner lives in `alloc`.
+    pub fn new_in(v: T, alloc: A) -> Self {
+        let layout = Layout::new::<RcInner<T>>();
+        let inner = match alloc.allocate(layout) {
+            Ok(mem) => mem.cast::<RcInner<T>>(),
+            Err(_) => alloc::handle_alloc_error(layout),
+        };
+        // SAFETY: the allocator gave us memory that fits an RcInner<T>.
+        unsafe {
+            inner.as_ptr().write(RcInner {
+                strong: Cell::new(1),
+                weak: Cell::new(1),
+                value: v,
+            })
+        };
+        Rc {
+            inner,
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    // Same as `pin`, but the RcInner lives in `alloc`. The allocator must be 'static, or it could
+    // be freed after a leaked Rc, reusing the memory of a pinned value that was never dropped.
+    pub fn pin_in(v: T, alloc: A) -> Pin<Rc<T, A>>
+    where
+        A: 'static,
+    {
+        // SAFETY: same as `pin`.
+        unsafe { Pin::new_unchecked(Rc::new_in(v, alloc)) }
+    }
+
+    // Takes the value out if this is the only Rc, otherwise gives the Rc back.
+    // Any Weak's left can't upgrade anymore.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if Rc::strong_count(&this) != 1 {
+            return Err(this);
+        }
+        let this = ManuallyDrop::new(this);
+        this.inner().strong.set(0);
+        // SAFETY: strong is 0 now, so nobody else can read the value.
+        // It's moved out and never dropped in place.
+        let value = unsafe { ptr::read(&this.inner().value) };
+        // The weak reference shared by the Rc's becomes a real Weak, dropping it gives the
+        // allocation back if there are no other Weak's.
+        // SAFETY: the allocator is moved out of the ManuallyDrop, which is never used again.
+        drop(Weak {
+            inner: Some(this.inner),
+            alloc: unsafe { ptr::read(&this.alloc) },
+        });
+        Ok(value)
+    }
+
+    // Same as `try_unwrap` but drops the Rc if it isn't the only one.
+    pub fn into_inner(this: Self) -> Option<T> {
+        Rc::try_unwrap(this).ok()
+    }
+
+    // Clone on write, clones the value into a new allocation if there are other Rc's.
+    // If only Weak's point to it the value is moved out instead, and the Weak's can't upgrade anymore.
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+        A: Clone,
+    {
+        if Rc::strong_count(this) != 1 {
+            *this = Rc::new_in((**this).clone(), this.alloc.clone());
+        } else if Rc::weak_count(this) != 0 {
+            this.inner().strong.set(0);
+            // SAFETY: strong is 0 now so the Weak's never see the value again,
+            // and it's moved out of the old allocation without being dropped in place.
+            let fresh = Rc::new_in(
+                unsafe { ptr::read(&this.inner().value) },
+                this.alloc.clone(),
+            );
+            // Overwrite without running Drop, the old Rc's weak reference is given up as a Weak.
+            let old = ManuallyDrop::new(mem::replace(this, fresh));
+            // SAFETY: the allocator is moved out of the ManuallyDrop, which is never used again.
+            drop(Weak {
+                inner: Some(old.inner),
+                alloc: unsafe { ptr::read(&old.alloc) },
+            });
+        }
+        // SAFETY: we are now the only Rc and there are no Weak's, and we hold a &mut to that Rc.
+        unsafe { &mut (*this.inner.as_ptr()).value }
+    }
 }
 
-impl<T> std::ops::Deref for Rc<T> {
+impl<T: ?Sized, A: Allocator> Rc<T, A> {
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        // Only the addresses, the same value can be reached through different vtables.
+        ptr::addr_eq(this.inner.as_ptr(), other.inner.as_ptr())
+    }
+
+    // Mutable access to the value, only if no other Rc or Weak points to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if Rc::strong_count(this) == 1 && Rc::weak_count(this) == 0 {
+            // SAFETY: we are the only Rc and there are no Weak's that could upgrade,
+            // and we hold a &mut to that Rc, so nobody else can get to the value.
+            Some(unsafe { &mut (*this.inner.as_ptr()).value })
+        } else {
+            None
+        }
+    }
+
+    pub fn allocator(this: &Self) -> &A {
+        &this.alloc
+    }
+
+    // Gives up the Rc without decrementing strong, the pointer has to go back through `from_raw`
+    // for the value to be dropped. The allocator is forgotten, `from_raw_in` takes it back.
+    pub fn into_raw(this: Self) -> *const T {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: the allocation is alive, and stays alive since we never give up our count.
+        unsafe { ptr::addr_of!((*this.inner.as_ptr()).value) }
+    }
+
+    // Same as `into_raw`, but hands the allocator back too instead of forgetting it.
+    pub fn into_raw_with_allocator(this: Self) -> (*const T, A) {
+        let this = ManuallyDrop::new(this);
+        // SAFETY: the allocator is moved out of the ManuallyDrop, which is never used again.
+        let alloc = unsafe { ptr::read(&this.alloc) };
+        // SAFETY: same as `into_raw`.
+        let ptr = unsafe { ptr::addr_of!((*this.inner.as_ptr()).value) };
+        (ptr, alloc)
+    }
+
+    // Gives up the Rc for good, the value is never dropped and the allocation never freed.
+    // Other Rc's and Weak's to it keep working, strong just never gets back to 0.
+    pub fn leak<'a>(this: Self) -> &'a T
+    where
+        A: 'a,
+    {
+        // SAFETY: the Rc's count is never given back, so the value outlives any 'a.
+        // A: 'a is there because the allocation lives in the allocator.
+        unsafe { &*Rc::into_raw(this) }
+    }
+
+    /// Same as `from_raw`, for an Rc whose RcInner lives in `alloc`.
+    ///
+    /// # Safety
+    /// Same as `from_raw`, and `alloc` must be the allocator the Rc was made with, or a clone of it.
+    pub unsafe fn from_raw_in(ptr: *const T, alloc: A) -> Self {
+        // The value is still alive, we stand for one of its Rc's, so we can look up its alignment.
+        let offset = data_offset(mem::align_of_val(&*ptr));
+        // byte_sub keeps the metadata of a fat pointer, the length or vtable.
+        let inner = ptr.byte_sub(offset) as *mut RcInner<T>;
+        Rc {
+            inner: NonNull::new_unchecked(inner),
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn downgrade(this: &Self) -> Weak<T, A>
+    where
+        A: Clone,
+    {
+        increment(&this.inner().weak);
+        Weak {
+            inner: Some(this.inner),
+            alloc: this.alloc.clone(),
+        }
+    }
+
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.get()
+    }
+
+    pub fn weak_count(this: &Self) -> usize {
+        // The Rc's share one weak reference between them, which isn't a Weak.
+        this.inner().weak.get() - 1
+    }
+
+    fn inner(&self) -> &RcInner<T> {
+        // SAFETY: the allocation lives at least as long as any Rc pointing to it.
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+impl<T: ?Sized> Rc<T> {
+    /// Takes back an Rc that was given up with `into_raw`.
+    ///
+    /// # Safety
+    /// `ptr` must come from `Rc::<T>::into_raw`, or from `Rc::<U>::into_raw` followed by an
+    /// unsizing coercion to `*const T`, see `unsize_rc!`.
+    /// Each pointer given out by `into_raw` must only be turned back into an Rc once.
+    pub unsafe fn from_raw(ptr: *const T) -> Rc<T> {
+        Rc::from_raw_in(ptr, Global)
+    }
+
+    /// Adds one to the strong count of the Rc behind `ptr`, as if an Rc was cloned and given up
+    /// with `into_raw`. Aborts if the count would overflow, same as `clone`.
+    ///
+    /// # Safety
+    /// `ptr` must come from `Rc::<T>::into_raw`, and the Rc it stands for must still be alive.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        // Don't drop the Rc we borrow the count through, we don't own it.
+        let rc = ManuallyDrop::new(Rc::from_raw(ptr));
+        let _clone: ManuallyDrop<Rc<T>> = ManuallyDrop::new(Rc::clone(&rc));
+    }
+
+    /// Takes one from the strong count of the Rc behind `ptr`, dropping the value if it was the last.
+    ///
+    /// # Safety
+    /// Same as `from_raw`, this gives up the Rc that `ptr` stands for, so `ptr` must have been
+    /// counted by `into_raw` or `increment_strong_count`.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Rc::from_raw(ptr));
+    }
+}
+
+impl<T: ?Sized, A: Allocator> std::ops::Deref for Rc<T, A> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
         // SAFTEY: self.inner is a Box that is only dellocated when the last Rc goes away
         // we have an Rc, therefore the Box has not been dealloacted, so deref is fine.
-        &unsafe { self.inner.as_ref() }.value
+        &self.inner().value
     }
 }
 
-impl<T> Clone for Rc<T> {
+impl<T: ?Sized, A: Allocator + Clone> Clone for Rc<T, A> {
     fn clone(&self) -> Self {
-        let inner = unsafe { self.inner.as_ref() };
-        let c = inner.refcount.get();
-        inner.refcount.set(c + 1);
+        increment(&self.inner().strong);
         Rc {
             inner: self.inner,
+            alloc: self.alloc.clone(),
             _marker: PhantomData,
         }
     }
 }
 
-impl<T> Drop for Rc<T> {
+impl<T: ?Sized, A: Allocator> Drop for Rc<T, A> {
     fn drop(&mut self) {
-        let inner = unsafe { self.inner.as_ref() };
-        let c = inner.refcount.get();
+        let inner = self.inner();
+        let c = inner.strong.get();
+        inner.strong.set(c - 1);
         if c == 1 {
-            drop(inner);
             // SAFETY: we are the _only_ Rc left, and we are being dropped.
             // Therefore, after us, there will be no Rc's, and no references to T.
-            let _ = unsafe { Box::from_raw(self.inner.as_ptr()) };
-        } else {
-            // There are other Rcs, so count -1 and don't drop
-            inner.refcount.set(c - 1);
+            // Weak's can't upgrade anymore since strong is 0, so they never see the value again.
+            unsafe { ptr::drop_in_place(ptr::addr_of_mut!((*self.inner.as_ptr()).value)) };
+            // Give up the weak reference shared by all the Rc's.
+            // SAFETY: the allocation itself has not been freed yet, we still hold that weak reference.
+            unsafe { release_weak(self.inner, &self.alloc) };
         }
     }
 }
+
+// A non-owning pointer to an Rc allocation, it doesn't keep the value alive but keeps the
+// allocation around so that it can check whether the value is still there.
+// Used to break cycles such as a child pointing back to its parent.
+pub struct Weak<T: ?Sized, A: Allocator = Global> {
+    // None for a Weak made with `Weak::new`, which never had an allocation.
+    inner: Option<NonNull<RcInner<T>>>,
+    alloc: A,
+}
+
+impl<T: ?Sized> Weak<T> {
+    pub fn new() -> Self {
+        Weak {
+            inner: None,
+            alloc: Global,
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Weak<T, A> {
+    pub fn upgrade(&self) -> Option<Rc<T, A>>
+    where
+        A: Clone,
+    {
+        let inner = self.inner()?;
+        if inner.strong.get() == 0 {
+            // The value has already been dropped.
+            return None;
+        }
+        increment(inner.strong);
+        Some(Rc {
+            inner: self.inner?,
+            alloc: self.alloc.clone(),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn strong_count(&self) -> usize {
+        self.inner().map_or(0, |inner| inner.strong.get())
+    }
+
+    pub fn weak_count(&self) -> usize {
+        match self.inner() {
+            Some(inner) if inner.strong.get() > 0 => inner.weak.get() - 1,
+            // Same as std, there is nothing to count once the value is gone.
+            _ => 0,
+        }
+    }
+
+    fn inner(&self) -> Option<WeakInner<'_>> {
+        // SAFETY: the allocation is only freed once the last Weak is gone, and we are a Weak.
+        // Only references to the counts are made, since the value may already have been dropped.
+        self.inner.map(|inner| unsafe {
+            WeakInner {
+                strong: &*ptr::addr_of!((*inner.as_ptr()).strong),
+                weak: &*ptr::addr_of!((*inner.as_ptr()).weak),
+            }
+        })
+    }
+}
+
+// The counts of an RcInner, which stay valid for as long as the allocation does.
+struct WeakInner<'a> {
+    strong: &'a Cell<usize>,
+    weak: &'a Cell<usize>,
+}
+
+impl<T: ?Sized> Default for Weak<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized, A: Allocator + Clone> Clone for Weak<T, A> {
+    fn clone(&self) -> Self {
+        if let Some(inner) = self.inner() {
+            increment(inner.weak);
+        }
+        Weak {
+            inner: self.inner,
+            alloc: self.alloc.clone(),
+        }
+    }
+}
+
+impl<T: ?Sized, A: Allocator> Drop for Weak<T, A> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner {
+            // SAFETY: we hold a weak reference, so the allocation is still around.
+            unsafe { release_weak(inner, &self.alloc) };
+        }
+    }
+}
+
+// Bumps a count, aborting instead of wrapping around like std does. `mem::forget` is safe, so
+// enough forgotten clones could otherwise bring the count back to 1 and free the value while
+// other Rc's still point to it. Panicking isn't enough, the count has to stay put while we unwind.
+fn increment(count: &Cell<usize>) {
+    let c = count.get();
+    if c == usize::MAX {
+        std::process::abort();
+    }
+    count.set(c + 1);
+}
+
+// Gives up one weak reference and frees the allocation if it was the last one.
+// SAFETY: the caller must own a weak reference to a live allocation made by an Rc constructor
+// from `alloc`, and the value must already have been dropped if this is the last weak reference.
+// The value must have been written at some point, see CyclicGuard for the one place it isn't.
+unsafe fn release_weak<T: ?Sized, A: Allocator>(inner: NonNull<RcInner<T>>, alloc: &A) {
+    let weak = &*ptr::addr_of!((*inner.as_ptr()).weak);
+    let c = weak.get();
+    weak.set(c - 1);
+    if c == 1 {
+        // Finding the layout of an unsized value needs a reference to it, Layout::for_value_raw
+        // isn't stable. Its size and alignment come from the pointer's metadata, not from the
+        // value's bytes, so it's fine that the value has been dropped already.
+        let layout = Layout::for_value(&*inner.as_ptr());
+        // Don't go through Box::from_raw, that would drop the value a second time.
+        alloc.deallocate(inner.cast(), layout);
+    }
+}
+
+// Where the value starts in an RcInner, for a value with the given alignment.
+fn data_offset(align: usize) -> usize {
+    // RcInner is repr(C), so the value comes after the counts, padded to its alignment.
+    Layout::new::<RcInner<()>>().size().next_multiple_of(align)
+}
+
+// Allocates an RcInner<[T]> for `len` elements with the counts set to one Rc,
+// the elements are left uninitialised for the caller to write.
+fn allocate_slice<T>(len: usize) -> NonNull<RcInner<[T]>> {
+    let layout = Layout::new::<RcInner<()>>()
+        .extend(Layout::array::<T>(len).expect("Rc<[T]> too large"))
+        .expect("Rc<[T]> too large")
+        .0
+        .pad_to_align();
+    let mem = match Global.allocate(layout) {
+        Ok(mem) => mem,
+        Err(_) => alloc::handle_alloc_error(layout),
+    };
+    // A *mut [T] cast to *mut RcInner<[T]> keeps `len` as the metadata.
+    let inner = ptr::slice_from_raw_parts_mut(mem.as_ptr() as *mut T, len) as *mut RcInner<[T]>;
+    // SAFETY: the allocation has the layout of an RcInner<[T]> of `len`.
+    unsafe {
+        ptr::addr_of_mut!((*inner).strong).write(Cell::new(1));
+        ptr::addr_of_mut!((*inner).weak).write(Cell::new(1));
+        NonNull::new_unchecked(inner)
+    }
+}
+
+impl<T> From<Vec<T>> for Rc<[T]> {
+    fn from(mut v: Vec<T>) -> Self {
+        let inner = allocate_slice::<T>(v.len());
+        // SAFETY: the new allocation has room for exactly v.len() elements, and the elements are
+        // moved, not copied, since the Vec forgets about them before it's dropped.
+        unsafe {
+            let value = ptr::addr_of_mut!((*inner.as_ptr()).value) as *mut T;
+            ptr::copy_nonoverlapping(v.as_ptr(), value, v.len());
+            v.set_len(0);
+        }
+        Rc {
+            inner,
+            alloc: Global,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone> From<&[T]> for Rc<[T]> {
+    fn from(v: &[T]) -> Self {
+        // Clone into a Vec first, so a panicking clone is cleaned up by the Vec.
+        Rc::from(v.to_vec())
+    }
+}
+
+impl From<&str> for Rc<str> {
+    fn from(v: &str) -> Self {
+        let rc = Rc::<[u8]>::from(v.as_bytes().to_vec());
+        // SAFETY: the bytes came from a str so they are valid UTF-8,
+        // and [u8] and str have the same layout and the same metadata.
+        unsafe { Rc::from_raw(Rc::into_raw(rc) as *const str) }
+    }
+}
+
+impl From<String> for Rc<str> {
+    fn from(v: String) -> Self {
+        let rc = Rc::<[u8]>::from(v.into_bytes());
+        // SAFETY: same as From<&str>.
+        unsafe { Rc::from_raw(Rc::into_raw(rc) as *const str) }
+    }
+}
+
+// The same impls as std's Rc, comparisons and hashing go through to the value.
+impl<T: ?Sized + std::fmt::Debug, A: Allocator> std::fmt::Debug for Rc<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Display, A: Allocator> std::fmt::Display for Rc<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized, A: Allocator> std::fmt::Pointer for Rc<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Pointer::fmt(&(&**self as *const T), f)
+    }
+}
+
+impl<T: Default> Default for Rc<T> {
+    fn default() -> Self {
+        Rc::new(T::default())
+    }
+}
+
+impl Default for Rc<str> {
+    fn default() -> Self {
+        Rc::from("")
+    }
+}
+
+impl<T> Default for Rc<[T]> {
+    fn default() -> Self {
+        Rc::from(Vec::new())
+    }
+}
+
+impl<T> From<T> for Rc<T> {
+    fn from(value: T) -> Self {
+        Rc::new(value)
+    }
+}
+
+impl<T: ?Sized + PartialEq, A: Allocator> PartialEq for Rc<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq, A: Allocator> Eq for Rc<T, A> {}
+
+impl<T: ?Sized + PartialOrd, A: Allocator> PartialOrd for Rc<T, A> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Ord, A: Allocator> Ord for Rc<T, A> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + std::hash::Hash, A: Allocator> std::hash::Hash for Rc<T, A> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+// Hashes and compares the same as T, so an Rc<str> key can be looked up with a &str.
+impl<T: ?Sized, A: Allocator> std::borrow::Borrow<T> for Rc<T, A> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, A: Allocator> AsRef<T> for Rc<T, A> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized, A: Allocator> std::fmt::Debug for Weak<T, A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
+// Turns an Rc<T> into an Rc<U> where a T can be unsized into a U, e.g. into an Rc<dyn Trait>.
+// Stands in for the unstable CoerceUnsized, which is what lets std's Rc coerce implicitly.
+// The pointer is only ever coerced, never cast, so the compiler checks the conversion.
+//
+//     let shown: Rc<dyn Display> = unsize_rc!(Rc::new(5) => dyn Display);
+#[macro_export]
+macro_rules! unsize_rc {
+    ($rc:expr => $ty:ty) => {{
+        // The allocator comes along, the Rc may not live in Global.
+        let (ptr, alloc) = $crate::rc::Rc::into_raw_with_allocator($rc);
+        let ptr: *const $ty = ptr;
+        // SAFETY: the pointer came from into_raw_with_allocator with this allocator,
+        // and was only unsized on the way.
+        unsafe { $crate::rc::Rc::<$ty, _>::from_raw_in(ptr, alloc) }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rc, Weak};
+    use crate::allocator::{AllocError, Allocator, Global};
+    use crate::refcell::RefCell;
+    use crate::test_util::{assert_aborts, in_abort_child, DropCounter};
+    use std::alloc::Layout;
+    use std::pin::Pin;
+    use std::ptr::NonNull;
+
+    #[test]
+    fn counts() {
+        let a = Rc::new(5);
+        assert_eq!(Rc::strong_count(&a), 1);
+        assert_eq!(Rc::weak_count(&a), 0);
+        let b = Rc::clone(&a);
+        let w = Rc::downgrade(&a);
+        assert_eq!(Rc::strong_count(&a), 2);
+        assert_eq!(Rc::weak_count(&a), 1);
+        assert_eq!(w.strong_count(), 2);
+        assert_eq!(w.weak_count(), 1);
+        drop(b);
+        drop(a);
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 0);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn upgrade_keeps_value_alive() {
+        let a = Rc::new(String::from("hello"));
+        let w = Rc::downgrade(&a);
+        let b = w.upgrade().unwrap();
+        drop(a);
+        assert_eq!(&*b, "hello");
+        assert_eq!(w.upgrade().as_deref().map(String::as_str), Some("hello"));
+        drop(b);
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn value_dropped_before_allocation() {
+        let dropped = std::cell::Cell::new(0);
+        let a = Rc::new(DropCounter(&dropped));
+        let w1 = Rc::downgrade(&a);
+        let w2 = w1.clone();
+        drop(a);
+        // The value goes away with the last Rc, even though Weak's are still around.
+        assert_eq!(dropped.get(), 1);
+        drop(w1);
+        drop(w2);
+        assert_eq!(dropped.get(), 1);
+    }
+
+    #[test]
+    fn try_unwrap() {
+        let a = Rc::new(String::from("hello"));
+        let b = Rc::clone(&a);
+        let a = Rc::try_unwrap(a).unwrap_err();
+        assert!(Rc::into_inner(b).is_none());
+        let w = Rc::downgrade(&a);
+        assert_eq!(Rc::try_unwrap(a).ok().unwrap(), "hello");
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut a = Rc::new(1);
+        *Rc::get_mut(&mut a).unwrap() += 1;
+        let b = Rc::clone(&a);
+        assert!(Rc::get_mut(&mut a).is_none());
+        drop(b);
+        let w = Rc::downgrade(&a);
+        assert!(Rc::get_mut(&mut a).is_none());
+        drop(w);
+        assert_eq!(Rc::get_mut(&mut a), Some(&mut 2));
+    }
+
+    #[test]
+    fn make_mut() {
+        let mut a = Rc::new(vec![1]);
+        Rc::make_mut(&mut a).push(2);
+        let b = Rc::clone(&a);
+        Rc::make_mut(&mut a).push(3);
+        assert_eq!(*a, [1, 2, 3]);
+        assert_eq!(*b, [1, 2]);
+        assert!(!Rc::ptr_eq(&a, &b));
+        assert!(Rc::ptr_eq(&b, &b.clone()));
+    }
+
+    #[test]
+    fn make_mut_disassociates_weak() {
+        let dropped = std::cell::Cell::new(0);
+        #[derive(Clone)]
+        struct Cloned<'a>(Vec<i32>, &'a std::cell::Cell<usize>);
+        impl Drop for Cloned<'_> {
+            fn drop(&mut self) {
+                self.1.set(self.1.get() + 1);
+            }
+        }
+        let mut a = Rc::new(Cloned(vec![1], &dropped));
+        let w = Rc::downgrade(&a);
+        Rc::make_mut(&mut a).0.push(2);
+        // Moved, not cloned and dropped.
+        assert_eq!(dropped.get(), 0);
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.weak_count(), 0);
+        assert_eq!(Rc::weak_count(&a), 0);
+        assert_eq!(a.0, [1, 2]);
+        drop(a);
+        assert_eq!(dropped.get(), 1);
+    }
+
+    #[test]
+    fn raw() {
+        let a = Rc::new(String::from("hello"));
+        let b = Rc::clone(&a);
+        let ptr = Rc::into_raw(b);
+        // SAFETY: ptr came from into_raw and the Rc it stands for is still alive.
+        assert_eq!(unsafe { &*ptr }, "hello");
+        assert_eq!(Rc::strong_count(&a), 2);
+        // SAFETY: ptr came from into_raw and is only turned back once.
+        let b = unsafe { Rc::from_raw(ptr) };
+        assert!(Rc::ptr_eq(&a, &b));
+        drop(b);
+        assert_eq!(Rc::strong_count(&a), 1);
+    }
+
+    #[test]
+    fn strong_count_raw() {
+        let a = Rc::new(String::from("hello"));
+        let ptr = Rc::into_raw(Rc::clone(&a));
+        // SAFETY: ptr came from into_raw and the Rc it stands for is still alive,
+        // every increment is matched with a decrement.
+        unsafe {
+            Rc::increment_strong_count(ptr);
+            assert_eq!(Rc::strong_count(&a), 3);
+            Rc::decrement_strong_count(ptr);
+            Rc::decrement_strong_count(ptr);
+        }
+        assert_eq!(Rc::strong_count(&a), 1);
+        let w = Rc::downgrade(&a);
+        let ptr = Rc::into_raw(a);
+        // SAFETY: we gave up the last Rc, so this drops the value.
+        unsafe { Rc::decrement_strong_count(ptr) };
+        assert!(w.upgrade().is_none());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "leaks on purpose, miri.sh runs it with leaks ignored")]
+    fn leak() {
+        let dropped = std::cell::Cell::new(0);
+        let a = Rc::new(DropCounter(&dropped));
+        let w = Rc::downgrade(&a);
+        let b = Rc::clone(&a);
+        let leaked: &DropCounter<_> = Rc::leak(a);
+        drop(b);
+        assert_eq!(dropped.get(), 0);
+        assert!(std::ptr::eq(leaked, &*w.upgrade().unwrap()));
+        assert_eq!(w.strong_count(), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "leaks on purpose, miri.sh runs it with leaks ignored")]
+    fn forget_many() {
+        let a = Rc::new(());
+        for _ in 0..1000 {
+            std::mem::forget(Rc::clone(&a));
+        }
+        assert_eq!(Rc::strong_count(&a), 1001);
+    }
+
+    // Getting to usize::MAX with real clones would take forever, so the tests below start
+    // one short of it, as if that many Rc's had been forgotten.
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn clone_overflow_aborts() {
+        assert_aborts("rc::tests::clone_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by clone_overflow_aborts"]
+    fn clone_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Rc::new(());
+        a.inner().strong.set(usize::MAX);
+        let _ = Rc::clone(&a);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn upgrade_overflow_aborts() {
+        assert_aborts("rc::tests::upgrade_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by upgrade_overflow_aborts"]
+    fn upgrade_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Rc::new(());
+        let w = Rc::downgrade(&a);
+        a.inner().strong.set(usize::MAX);
+        let _ = w.upgrade();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn downgrade_overflow_aborts() {
+        assert_aborts("rc::tests::downgrade_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by downgrade_overflow_aborts"]
+    fn downgrade_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Rc::new(());
+        a.inner().weak.set(usize::MAX);
+        let _ = Rc::downgrade(&a);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn increment_strong_count_overflow_aborts() {
+        assert_aborts("rc::tests::increment_strong_count_overflow");
+    }
+
+    #[test]
+    #[ignore = "aborts, run by increment_strong_count_overflow_aborts"]
+    fn increment_strong_count_overflow() {
+        if !in_abort_child() {
+            return;
+        }
+        let a = Rc::new(());
+        a.inner().strong.set(usize::MAX);
+        let ptr = Rc::into_raw(a);
+        // SAFETY: ptr came from into_raw and the Rc is still alive, the overflow is what's tested.
+        unsafe { Rc::increment_strong_count(ptr) };
+    }
+
+    struct Cyclic {
+        me: Weak<Cyclic>,
+        upgraded_during_init: bool,
+    }
+
+    #[test]
+    fn new_cyclic() {
+        let a = Rc::new_cyclic(|me| Cyclic {
+            me: me.clone(),
+            upgraded_during_init: me.upgrade().is_some(),
+        });
+        assert!(!a.upgraded_during_init);
+        assert!(Rc::ptr_eq(&a, &a.me.upgrade().unwrap()));
+        assert_eq!(Rc::strong_count(&a), 1);
+        assert_eq!(Rc::weak_count(&a), 1);
+    }
+
+    #[test]
+    fn new_cyclic_panic() {
+        let result = std::panic::catch_unwind(|| {
+            Rc::<String>::new_cyclic(|_| panic!("no value"));
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "leaks on purpose, miri.sh runs it with leaks ignored")]
+    fn new_cyclic_panic_kept_weak() {
+        let kept = std::cell::RefCell::new(None);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            Rc::<String>::new_cyclic(|me| {
+                *kept.borrow_mut() = Some(me.clone());
+                panic!("no value")
+            });
+        }));
+        assert!(result.is_err());
+        // Everything a Weak can do only looks at the counts, never at the unwritten value.
+        let kept = kept.into_inner().unwrap();
+        assert!(kept.upgrade().is_none());
+        assert_eq!(kept.strong_count(), 0);
+        assert_eq!(kept.weak_count(), 0);
+        let again = kept.clone();
+        drop(kept);
+        assert!(again.upgrade().is_none());
+        // The allocation leaks rather than being freed through a Weak that never had a value.
+        drop(again);
+    }
+
+    #[test]
+    fn str() {
+        let a: Rc<str> = Rc::from("hello");
+        let b: Rc<str> = Rc::from(String::from("hello"));
+        assert_eq!(&*a, "hello");
+        assert_eq!(&*a, &*b);
+        let w = Rc::downgrade(&a);
+        let ptr = Rc::into_raw(a);
+        // SAFETY: ptr came from into_raw and is only turned back once.
+        let a = unsafe { Rc::from_raw(ptr) };
+        assert!(Rc::ptr_eq(&a, &w.upgrade().unwrap()));
+        assert_eq!(&*Rc::<str>::from(""), "");
+    }
+
+    #[test]
+    fn slice() {
+        let dropped = std::cell::Cell::new(0);
+        let v: Vec<_> = (0..5).map(|_| DropCounter(&dropped)).collect();
+        let a: Rc<[DropCounter<_>]> = Rc::from(v);
+        assert_eq!(a.len(), 5);
+        // Moved out of the Vec, not dropped with it.
+        assert_eq!(dropped.get(), 0);
+        let w = Rc::downgrade(&a);
+        drop(a);
+        assert_eq!(dropped.get(), 5);
+        drop(w);
+
+        let b: Rc<[String]> = Rc::from(&[String::from("a"), String::from("b")][..]);
+        assert_eq!(&*b, ["a", "b"]);
+        let empty: Rc<[u8]> = Rc::from(Vec::new());
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn slice_layout() {
+        #[repr(align(32))]
+        #[derive(Clone, Copy)]
+        struct Aligned(u8);
+        let a: Rc<[Aligned]> = Rc::from(&[Aligned(1), Aligned(2), Aligned(3)][..]);
+        assert_eq!(a.as_ptr() as usize % 32, 0);
+        assert_eq!(a[2].0, 3);
+        let b: Rc<[u8]> = Rc::from(&[1, 2, 3][..]);
+        // The bytes come right after the two counts.
+        let ptr = Rc::into_raw(b);
+        // SAFETY: ptr came from into_raw and is only turned back once.
+        let b = unsafe { Rc::from_raw(ptr) };
+        assert_eq!(
+            ptr as *const u8 as usize - b.inner.as_ptr() as *const u8 as usize,
+            2 * std::mem::size_of::<usize>()
+        );
+    }
+
+    trait Shape {
+        fn area(&self) -> u32;
+    }
+    struct Square<'a> {
+        side: u32,
+        _dropped: DropCounter<&'a std::cell::Cell<usize>>,
+    }
+    impl Shape for Square<'_> {
+        fn area(&self) -> u32 {
+            self.side * self.side
+        }
+    }
+
+    #[test]
+    fn dyn_trait() {
+        let dropped = std::cell::Cell::new(0);
+        let square = Rc::new(Square {
+            side: 3,
+            _dropped: DropCounter(&dropped),
+        });
+        let shape: Rc<dyn Shape> = unsize_rc!(Rc::clone(&square) => dyn Shape);
+        assert_eq!(shape.area(), 9);
+        assert_eq!(Rc::strong_count(&shape), 2);
+        let w = Rc::downgrade(&shape);
+        drop(square);
+        assert_eq!(w.upgrade().unwrap().area(), 9);
+        drop(shape);
+        assert_eq!(dropped.get(), 1);
+        assert!(w.upgrade().is_none());
+
+        let shown: Rc<dyn std::fmt::Display> = unsize_rc!(Rc::new(5) => dyn std::fmt::Display);
+        assert_eq!(shown.to_string(), "5");
+        let slice: Rc<[i32]> = unsize_rc!(Rc::new([1, 2, 3]) => [i32]);
+        assert_eq!(&*slice, [1, 2, 3]);
+    }
+
+    #[derive(Default)]
+    struct CountingAlloc {
+        allocs: std::cell::Cell<usize>,
+        deallocs: std::cell::Cell<usize>,
+    }
+
+    unsafe impl Allocator for CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+            self.allocs.set(self.allocs.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.deallocs.set(self.deallocs.get() + 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn allocator() {
+        let counter = CountingAlloc::default();
+        let a = Rc::new_in(String::from("hello"), &counter);
+        let b = Rc::clone(&a);
+        let w = Rc::downgrade(&a);
+        assert_eq!(counter.allocs.get(), 1);
+        assert!(std::ptr::eq(*Rc::allocator(&b), &counter));
+        drop(a);
+        drop(b);
+        // The Weak keeps the allocation around.
+        assert_eq!(counter.deallocs.get(), 0);
+        assert!(w.upgrade().is_none());
+        drop(w);
+        assert_eq!(counter.deallocs.get(), 1);
+    }
+
+    #[test]
+    fn allocator_unwrap_and_make_mut() {
+        let counter = CountingAlloc::default();
+        let a = Rc::new_in(5, &counter);
+        assert_eq!(Rc::try_unwrap(a).ok(), Some(5));
+        assert_eq!(counter.deallocs.get(), 1);
+
+        let mut c = Rc::new_in(vec![1], &counter);
+        let d = Rc::clone(&c);
+        Rc::make_mut(&mut c).push(2);
+        // The copy is made in the same allocator.
+        assert_eq!(counter.allocs.get(), 3);
+        let w = Rc::downgrade(&d);
+        drop(d);
+        drop(w);
+        let w = Rc::downgrade(&c);
+        Rc::make_mut(&mut c).push(3);
+        assert_eq!(counter.allocs.get(), 4);
+        drop(w);
+        assert_eq!(*c, [1, 2, 3]);
+        drop(c);
+        assert_eq!(counter.allocs.get(), counter.deallocs.get());
+    }
+
+    #[test]
+    fn allocator_unsize() {
+        let counter = CountingAlloc::default();
+        let shown: Rc<dyn std::fmt::Display, _> =
+            unsize_rc!(Rc::new_in(5, &counter) => dyn std::fmt::Display);
+        assert_eq!(shown.to_string(), "5");
+        assert!(std::ptr::eq(*Rc::allocator(&shown), &counter));
+        drop(shown);
+        // Freed where it was allocated, not in Global.
+        assert_eq!(counter.allocs.get(), 1);
+        assert_eq!(counter.deallocs.get(), 1);
+    }
+
+    // Points at itself, so moving it would leave the pointer dangling.
+    struct SelfRef<'a> {
+        me: std::cell::Cell<*const SelfRef<'a>>,
+        moved: &'a std::cell::Cell<bool>,
+        _pinned: std::marker::PhantomPinned,
+    }
+
+    impl SelfRef<'_> {
+        fn init(self: Pin<&Self>) {
+            self.me.set(&*self);
+        }
+    }
+
+    impl Drop for SelfRef<'_> {
+        fn drop(&mut self) {
+            self.moved.set(!std::ptr::eq(self.me.get(), self));
+        }
+    }
+
+    #[test]
+    fn pin() {
+        let moved = std::cell::Cell::new(true);
+        let a = Rc::pin(SelfRef {
+            me: std::cell::Cell::new(std::ptr::null()),
+            moved: &moved,
+            _pinned: std::marker::PhantomPinned,
+        });
+        a.as_ref().init();
+        let addr: *const SelfRef = &*a;
+        let b = Pin::clone(&a);
+        let c = b.clone();
+        drop(a);
+        // Moving the Pin<Rc> around doesn't move the value.
+        let moved_rc = vec![c];
+        assert!(std::ptr::eq(&*moved_rc[0], addr));
+        assert!(std::ptr::eq(&*b, addr));
+        assert!(std::ptr::eq(b.me.get(), addr));
+        drop(b);
+        drop(moved_rc);
+        // Still where it was pinned when it got dropped.
+        assert!(!moved.get());
+    }
+
+    #[test]
+    fn pin_in() {
+        static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        #[derive(Clone)]
+        struct Static;
+        unsafe impl Allocator for Static {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+                COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                Global.deallocate(ptr, layout)
+            }
+        }
+        let a = Rc::pin_in(std::marker::PhantomPinned, Static);
+        let b = Pin::clone(&a);
+        assert!(std::ptr::eq(&*a, &*b));
+        assert_eq!(COUNTER.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn traits() {
+        use std::collections::HashMap;
+        let a: Rc<str> = Rc::from("a");
+        let b: Rc<str> = Rc::from(String::from("b"));
+        let mut map = HashMap::new();
+        map.insert(Rc::clone(&a), 1);
+        map.insert(b, 2);
+        // Borrow<str> lets us look up with a plain &str.
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(map[&Rc::from("b")], 2);
+
+        // Equality is by value, not by pointer.
+        let x = Rc::new(5);
+        let y: Rc<i32> = Rc::from(5);
+        assert_eq!(x, y);
+        assert!(!Rc::ptr_eq(&x, &y));
+        assert!(Rc::new(4) < x);
+        assert_eq!(format!("{:?} {}", x, a), "5 a");
+        assert_eq!(format!("{:p}", x), format!("{:p}", &*x));
+        assert_eq!(format!("{:?}", Rc::downgrade(&x)), "(Weak)");
+        assert_eq!(*Rc::<Vec<i32>>::default(), Vec::<i32>::new());
+        assert_eq!(&*Rc::<str>::default(), "");
+        assert!(Rc::<[u8]>::default().is_empty());
+        let r: &i32 = x.as_ref();
+        assert_eq!(*r, 5);
+    }
+
+    #[test]
+    fn empty_weak() {
+        let w: Weak<i32> = Weak::new();
+        assert!(w.upgrade().is_none());
+        assert_eq!(w.strong_count(), 0);
+        assert_eq!(w.weak_count(), 0);
+        let _ = w.clone();
+    }
+
+    struct Node<'a> {
+        parent: RefCell<Weak<Node<'a>>>,
+        children: RefCell<Vec<Rc<Node<'a>>>>,
+        _dropped: DropCounter<&'a std::cell::Cell<usize>>,
+    }
+
+    fn node<'a>(dropped: &'a std::cell::Cell<usize>) -> Rc<Node<'a>> {
+        Rc::new(Node {
+            parent: RefCell::new(Weak::new()),
+            children: RefCell::new(Vec::new()),
+            _dropped: DropCounter(dropped),
+        })
+    }
+
+    #[test]
+    fn tree_with_parent_pointers() {
+        let dropped = std::cell::Cell::new(0);
+        let root = node(&dropped);
+        for _ in 0..3 {
+            let child = node(&dropped);
+            *child.parent.borrow_mut() = Rc::downgrade(&root);
+            root.children.borrow_mut().push(child);
+        }
+        assert_eq!(Rc::strong_count(&root), 1);
+        assert_eq!(Rc::weak_count(&root), 3);
+
+        let leaf = Rc::clone(&root.children.borrow()[0]);
+        let parent = leaf.parent.borrow().upgrade().unwrap();
+        assert!(std::ptr::eq(&*parent, &*root));
+        drop(parent);
+
+        // The children only point back weakly, so the whole tree goes away with the root.
+        drop(root);
+        assert_eq!(dropped.get(), 3);
+        assert!(leaf.parent.borrow().upgrade().is_none());
+        drop(leaf);
+        assert_eq!(dropped.get(), 4);
+    }
+
+    #[test]
+    fn strong_cycle_leaks() {
+        // A parent pointer that is an Rc keeps the cycle alive, which is what Weak avoids.
+        let dropped = std::cell::Cell::new(0);
+        struct Strong<'a> {
+            other: RefCell<Option<Rc<Strong<'a>>>>,
+            _dropped: DropCounter<&'a std::cell::Cell<usize>>,
+        }
+        let a = Rc::new(Strong {
+            other: RefCell::new(None),
+            _dropped: DropCounter(&dropped),
+        });
+        *a.other.borrow_mut() = Some(Rc::clone(&a));
+        let w = Rc::downgrade(&a);
+        drop(a);
+        assert_eq!(dropped.get(), 0);
+        // Break the cycle by hand so the test doesn't leak.
+        let a = w.upgrade().unwrap();
+        a.other.borrow_mut().take();
+        drop(a);
+        assert_eq!(dropped.get(), 1);
+    }
+}
@@ -1,4 +1,8 @@
 use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+#[cfg(feature = "debug-borrows")]
+use std::panic::Location;
+use std::ptr::NonNull;
 
 use crate::cell::Cell;
 // Performs mutation on immutabke references.
@@ -10,12 +14,17 @@ use crate::cell::Cell;
 pub struct RefCell<T> {
     value: UnsafeCell<T>,
     state: Cell<RefState>,
+    // Where the borrow that took the cell out of Unshared was made, to report on a conflict.
+    // Like std's `-Zdebug-refcell`, later shared borrows don't overwrite it.
+    #[cfg(feature = "debug-borrows")]
+    borrowed_at: Cell<Option<&'static Location<'static>>>,
 }
 #[derive(Clone, Copy)]
 enum RefState {
     Unshared,
     Shared(usize),
-    Exclusive,
+    // Number of RefMut's splitting the one exclusive borrow between them, see `RefMut::map_split`.
+    Exclusive(usize),
 }
 
 // impl<T> !Sync for Cell<T> {} //  Implied by UnsafeCell
@@ -24,98 +33,616 @@ impl<T> RefCell<T> {
         Self {
             value: UnsafeCell::new(value),
             state: Cell::new(RefState::Unshared),
+            #[cfg(feature = "debug-borrows")]
+            borrowed_at: Cell::new(None),
         }
     }
-    pub fn borrow(&self) -> Option<Ref<'_, T>> {
+    #[track_caller]
+    pub fn try_borrow(&self) -> Result<Ref<'_, T>, BorrowError> {
         match self.state.get() {
             RefState::Unshared => {
                 self.state.set(RefState::Shared(1));
-                Some(Ref { refcell: self })
+                #[cfg(feature = "debug-borrows")]
+                self.borrowed_at.set(Some(Location::caller()));
+                Ok(self.new_ref())
                 // SAFETY: no exclusive references have been given out since state would be Exclusive
                 // Some(unsafe { &*self.value.get() })
             }
             RefState::Shared(n) => {
                 self.state.set(RefState::Shared(n + 1));
-                Some(Ref { refcell: self })
+                Ok(self.new_ref())
                 // SAFETY: no exclusive references have been given out since state would be Exclusive
                 // Some(unsafe { &*self.value.get() })
             }
-            RefState::Exclusive => None,
+            RefState::Exclusive(_) => Err(BorrowError {
+                _private: (),
+                #[cfg(feature = "debug-borrows")]
+                location: self.borrowed_at(),
+            }),
         }
     }
-    pub fn borrow_mut(&self) -> Option<RefMut<'_, T>> {
+    #[track_caller]
+    pub fn try_borrow_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
         if let RefState::Unshared = self.state.get() {
-            self.state.set(RefState::Exclusive);
-            Some(RefMut { refcell: self })
+            self.state.set(RefState::Exclusive(1));
+            #[cfg(feature = "debug-borrows")]
+            self.borrowed_at.set(Some(Location::caller()));
+            Ok(RefMut {
+                // SAFETY: UnsafeCell does not give us a null pointer.
+                value: unsafe { NonNull::new_unchecked(self.value.get()) },
+                borrow: BorrowRefMut { state: &self.state },
+                _marker: PhantomData,
+            })
             // SAFETY: no other references have benn given out since state be be Shared or Exclusive
             // Some(unsafe { &mut *self.value.get() })
         } else {
-            None
+            Err(BorrowMutError {
+                _private: (),
+                #[cfg(feature = "debug-borrows")]
+                location: self.borrowed_at(),
+            })
         }
     }
+    // Same as std, panics instead of handing back an error.
+    #[track_caller]
+    pub fn borrow(&self) -> Ref<'_, T> {
+        match self.try_borrow() {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    #[track_caller]
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        match self.try_borrow_mut() {
+            Ok(r) => r,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // &mut self means nobody else can be holding a Ref or RefMut, no need to look at the state.
+        self.value.get_mut()
+    }
+
+    // A Ref or RefMut leaked with mem::forget leaves the cell borrowed forever, which is safe but
+    // stuck. This puts it back to Unshared, &mut self proves no guard is actually still alive.
+    pub fn undo_leak(&mut self) -> &mut T {
+        self.state.set(RefState::Unshared);
+        self.get_mut()
+    }
+
+    // The caller must already have counted this Ref in `state`.
+    fn new_ref(&self) -> Ref<'_, T> {
+        Ref {
+            // SAFETY: UnsafeCell does not give us a null pointer.
+            value: unsafe { NonNull::new_unchecked(self.value.get()) },
+            borrow: BorrowRef { state: &self.state },
+        }
+    }
+
+    #[cfg(feature = "debug-borrows")]
+    fn borrowed_at(&self) -> &'static Location<'static> {
+        // Only called on a conflict, so some borrow is live and has recorded where it was made.
+        self.borrowed_at
+            .get()
+            .expect("borrowed RefCell has no borrow location")
+    }
+}
+
+// Returned by `try_borrow` when the RefCell is already borrowed mutably.
+#[derive(Debug)]
+pub struct BorrowError {
+    _private: (),
+    #[cfg(feature = "debug-borrows")]
+    location: &'static Location<'static>,
+}
+
+#[cfg(feature = "debug-borrows")]
+impl BorrowError {
+    // Where the conflicting RefMut was made.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl std::fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already mutably borrowed")?;
+        #[cfg(feature = "debug-borrows")]
+        write!(f, "; a previous borrow was at {}", self.location)?;
+        Ok(())
+    }
+}
+
+impl std::error::Error for BorrowError {}
+
+// Returned by `try_borrow_mut` when the RefCell is already borrowed, mutably or not.
+#[derive(Debug)]
+pub struct BorrowMutError {
+    _private: (),
+    #[cfg(feature = "debug-borrows")]
+    location: &'static Location<'static>,
+}
+
+#[cfg(feature = "debug-borrows")]
+impl BorrowMutError {
+    // Where the first of the conflicting Ref's or the RefMut was made.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl std::fmt::Display for BorrowMutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("already borrowed")?;
+        #[cfg(feature = "debug-borrows")]
+        write!(f, "; a previous borrow was at {}", self.location)?;
+        Ok(())
+    }
+}
+
+impl std::error::Error for BorrowMutError {}
+
+// The same impls as std's RefCell, the ones that read the value borrow it and so panic
+// if it's mutably borrowed, except Debug which prints a placeholder instead.
+impl<T: std::fmt::Debug> std::fmt::Debug for RefCell<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        struct BorrowedPlaceholder;
+        impl std::fmt::Debug for BorrowedPlaceholder {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("<borrowed>")
+            }
+        }
+        let mut d = f.debug_struct("RefCell");
+        match self.try_borrow() {
+            Ok(borrow) => d.field("value", &&*borrow),
+            Err(_) => d.field("value", &BorrowedPlaceholder),
+        };
+        d.finish()
+    }
+}
+
+impl<T: Clone> Clone for RefCell<T> {
+    #[track_caller]
+    fn clone(&self) -> Self {
+        RefCell::new(self.borrow().clone())
+    }
+}
+
+impl<T: Default> Default for RefCell<T> {
+    fn default() -> Self {
+        RefCell::new(T::default())
+    }
+}
+
+impl<T> From<T> for RefCell<T> {
+    fn from(value: T) -> Self {
+        RefCell::new(value)
+    }
+}
+
+impl<T: PartialEq> PartialEq for RefCell<T> {
+    #[track_caller]
+    fn eq(&self, other: &Self) -> bool {
+        *self.borrow() == *other.borrow()
+    }
+}
+
+impl<T: Eq> Eq for RefCell<T> {}
+
+impl<T: PartialOrd> PartialOrd for RefCell<T> {
+    #[track_caller]
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.borrow().partial_cmp(&*other.borrow())
+    }
+}
+
+impl<T: Ord> Ord for RefCell<T> {
+    #[track_caller]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.borrow().cmp(&*other.borrow())
+    }
+}
+
+// Keeps the shared borrow of a RefCell alive and gives it back when dropped.
+// Separate from Ref so that a Ref to part of the value (see `Ref::map`) still releases the whole cell.
+struct BorrowRef<'refcell> {
+    state: &'refcell Cell<RefState>,
+}
+
+impl Clone for BorrowRef<'_> {
+    fn clone(&self) -> Self {
+        match self.state.get() {
+            RefState::Exclusive(_) | RefState::Unshared => unreachable!(),
+            RefState::Shared(n) => self.state.set(RefState::Shared(n + 1)),
+        }
+        BorrowRef { state: self.state }
+    }
+}
+
+impl Drop for BorrowRef<'_> {
+    fn drop(&mut self) {
+        match self.state.get() {
+            RefState::Exclusive(_) | RefState::Unshared => unreachable!(),
+            RefState::Shared(1) => {
+                self.state.set(RefState::Unshared);
+            }
+            RefState::Shared(n) => {
+                self.state.set(RefState::Shared(n - 1));
+            }
+        }
+    }
+}
+
+pub struct Ref<'refcell, T: ?Sized> {
+    // Points into the RefCell, at the whole value or some part of it.
+    value: NonNull<T>,
+    borrow: BorrowRef<'refcell>,
 }
 
-pub struct Ref<'refcell, T> {
-    refcell: &'refcell RefCell<T>,
+impl<'refcell, T: ?Sized> Ref<'refcell, T> {
+    // Another Ref to the same value, an associated function like std so it doesn't shadow T::clone.
+    #[allow(clippy::should_implement_trait)]
+    pub fn clone(orig: &Ref<'refcell, T>) -> Ref<'refcell, T> {
+        Ref {
+            value: orig.value,
+            borrow: orig.borrow.clone(),
+        }
+    }
+
+    // Narrows the Ref down to a part of the value, e.g. a field or an element.
+    pub fn map<U: ?Sized, F>(orig: Ref<'refcell, T>, f: F) -> Ref<'refcell, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        Ref {
+            value: NonNull::from(f(&*orig)),
+            borrow: orig.borrow,
+        }
+    }
+
+    // Like `map`, but gives the original Ref back if there is no such part.
+    pub fn filter_map<U: ?Sized, F>(
+        orig: Ref<'refcell, T>,
+        f: F,
+    ) -> Result<Ref<'refcell, U>, Ref<'refcell, T>>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        match f(&*orig) {
+            Some(value) => Ok(Ref {
+                value: NonNull::from(value),
+                borrow: orig.borrow,
+            }),
+            None => Err(orig),
+        }
+    }
 }
 
-impl<T> std::ops::Deref for Ref<'_, T> {
+impl<T: ?Sized> std::ops::Deref for Ref<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // SAFETY
         // a Ref is only created if no exclusive references have been given out.
         // Onve it is given out, state is set to Shared, so no exclusive references are give out.
         // So dereferencing into a shared reference is fine.
+        // A mapped Ref points at something borrowed from the value under the same Shared state.
 
-        unsafe { &*self.refcell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
-impl<T> Drop for Ref<'_, T> {
+
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Display> std::fmt::Display for Ref<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+// Keeps the exclusive borrow of a RefCell alive and gives it back when dropped.
+struct BorrowRefMut<'refcell> {
+    state: &'refcell Cell<RefState>,
+}
+
+impl BorrowRefMut<'_> {
+    // Only for `RefMut::map_split`, where the two RefMut's point at disjoint parts of the value.
+    fn split(&self) -> Self {
+        match self.state.get() {
+            RefState::Shared(_) | RefState::Unshared => unreachable!(),
+            RefState::Exclusive(n) => self.state.set(RefState::Exclusive(n + 1)),
+        }
+        BorrowRefMut { state: self.state }
+    }
+}
+
+impl Drop for BorrowRefMut<'_> {
     fn drop(&mut self) {
-        match self.refcell.state.get() {
-            RefState::Exclusive | RefState::Unshared => unreachable!(),
-            RefState::Shared(1) => {
-                self.refcell.state.set(RefState::Unshared);
+        match self.state.get() {
+            RefState::Shared(_) | RefState::Unshared => unreachable!(),
+            RefState::Exclusive(1) => {
+                self.state.set(RefState::Unshared);
             }
-            RefState::Shared(n) => {
-                self.refcell.state.set(RefState::Shared(n - 1));
+            RefState::Exclusive(n) => {
+                self.state.set(RefState::Exclusive(n - 1));
             }
         }
     }
 }
-pub struct RefMut<'refcell, T> {
-    refcell: &'refcell RefCell<T>,
+
+pub struct RefMut<'refcell, T: ?Sized> {
+    // Points into the RefCell, at the whole value or some part of it.
+    value: NonNull<T>,
+    borrow: BorrowRefMut<'refcell>,
+    // NonNull is covariant, a RefMut has to be invariant in T like &mut T.
+    _marker: PhantomData<&'refcell mut T>,
 }
 
-impl<T> std::ops::Deref for RefMut<'_, T> {
+impl<'refcell, T: ?Sized> RefMut<'refcell, T> {
+    // Narrows the RefMut down to a part of the value, e.g. a field or an element.
+    pub fn map<U: ?Sized, F>(mut orig: RefMut<'refcell, T>, f: F) -> RefMut<'refcell, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        RefMut {
+            value: NonNull::from(f(&mut *orig)),
+            borrow: orig.borrow,
+            _marker: PhantomData,
+        }
+    }
+
+    // Splits the RefMut into two for disjoint parts of the value, e.g. two fields or two halves
+    // of a slice. The RefCell stays exclusively borrowed until both are dropped, in either order.
+    pub fn map_split<U: ?Sized, V: ?Sized, F>(
+        mut orig: RefMut<'refcell, T>,
+        f: F,
+    ) -> (RefMut<'refcell, U>, RefMut<'refcell, V>)
+    where
+        F: FnOnce(&mut T) -> (&mut U, &mut V),
+    {
+        let borrow = orig.borrow.split();
+        let (a, b) = f(&mut *orig);
+        (
+            RefMut {
+                value: NonNull::from(a),
+                borrow,
+                _marker: PhantomData,
+            },
+            RefMut {
+                value: NonNull::from(b),
+                borrow: orig.borrow,
+                _marker: PhantomData,
+            },
+        )
+    }
+}
+
+impl<T: ?Sized> std::ops::Deref for RefMut<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // SAFETY
         // a RefMut is only created if no other references have been given out.
         // Onve it is given out, state is set to Exclusive, so no future references are give out.
         // So dereferencing into a shared reference is fine.
+        // Split RefMut's point at disjoint parts, since `f` handed out two &mut's.
 
-        unsafe { &*self.refcell.value.get() }
+        unsafe { self.value.as_ref() }
     }
 }
 
-impl<T> std::ops::DerefMut for RefMut<'_, T> {
+impl<T: ?Sized> std::ops::DerefMut for RefMut<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // SAFETY
         // a RefMut is only created if no other references have been given out.
         // Onve it is given out, state is set to Exclusive, so no future references are give out.
         // So dereferencing into a shared reference is fine.
-        unsafe { &mut *self.refcell.value.get() }
+        unsafe { self.value.as_mut() }
     }
 }
 
-impl<T> Drop for RefMut<'_, T> {
-    fn drop(&mut self) {
-        match self.refcell.state.get() {
-            RefState::Shared(_) | RefState::Unshared => unreachable!(),
-            RefState::Exclusive => {
-                self.refcell.state.set(RefState::Unshared);
+impl<T: ?Sized + std::fmt::Debug> std::fmt::Debug for RefMut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: ?Sized + std::fmt::Display> std::fmt::Display for RefMut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ref, RefCell, RefMut};
+
+    #[test]
+    fn shared_borrows() {
+        let x = RefCell::new(5);
+        let a = x.borrow();
+        let b = x.try_borrow().unwrap();
+        assert_eq!(*a + *b, 10);
+        assert!(x.try_borrow_mut().is_err());
+        drop(a);
+        assert!(x.try_borrow_mut().is_err());
+        drop(b);
+        *x.borrow_mut() += 1;
+        assert_eq!(*x.borrow(), 6);
+    }
+
+    #[test]
+    fn errors() {
+        let x = RefCell::new(());
+        let m = x.borrow_mut();
+        let e = x.try_borrow().err().unwrap();
+        assert!(e.to_string().starts_with("already mutably borrowed"));
+        let e = x.try_borrow_mut().err().unwrap();
+        assert!(e.to_string().starts_with("already borrowed"));
+        let _: &dyn std::error::Error = &e;
+        drop(m);
+        assert!(x.try_borrow().is_ok());
+    }
+
+    #[test]
+    fn map() {
+        let x = RefCell::new((String::from("hello"), vec![1, 2, 3]));
+        let s = Ref::map(x.borrow(), |(s, _)| s.as_str());
+        let s2 = Ref::clone(&s);
+        assert_eq!(&*s, "hello");
+        drop(s);
+        assert!(x.try_borrow_mut().is_err());
+        drop(s2);
+        {
+            let mut v = RefMut::map(x.borrow_mut(), |(_, v)| v);
+            v.push(4);
+            assert!(x.try_borrow().is_err());
+        }
+        assert_eq!(*Ref::map(x.borrow(), |(_, v)| &v[3]), 4);
+    }
+
+    #[test]
+    fn filter_map() {
+        let x = RefCell::new(vec![1, 2, 3]);
+        let r = Ref::filter_map(x.borrow(), |v| v.get(5)).err().unwrap();
+        let r = Ref::filter_map(r, |v| v.get(1)).ok().unwrap();
+        assert_eq!(*r, 2);
+        assert!(x.try_borrow_mut().is_err());
+        drop(r);
+        assert!(x.try_borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn map_split() {
+        let x = RefCell::new([1, 2, 3, 4]);
+        for first_dropped in 0..2 {
+            let (mut a, mut b) = RefMut::map_split(x.borrow_mut(), |v| v.split_at_mut(2));
+            a[0] += 10;
+            b[1] += 10;
+            assert!(x.try_borrow().is_err());
+            if first_dropped == 0 {
+                drop(a);
+                assert!(x.try_borrow().is_err());
+                drop(b);
+            } else {
+                drop(b);
+                assert!(x.try_borrow_mut().is_err());
+                drop(a);
             }
+            assert!(x.try_borrow_mut().is_ok());
         }
+        assert_eq!(*x.borrow(), [21, 2, 3, 24]);
+    }
+
+    #[test]
+    fn forget_ref() {
+        let mut x = RefCell::new(1);
+        std::mem::forget(x.borrow());
+        // A leaked Ref keeps the cell shared, it never lets a RefMut in.
+        assert!(x.try_borrow().is_ok());
+        assert!(x.try_borrow_mut().is_err());
+        *x.undo_leak() += 1;
+        assert_eq!(*x.borrow_mut(), 2);
+    }
+
+    #[test]
+    fn forget_ref_mut() {
+        let mut x = RefCell::new([1, 2]);
+        std::mem::forget(x.borrow_mut());
+        assert!(x.try_borrow().is_err());
+        assert!(x.try_borrow_mut().is_err());
+        x.undo_leak();
+        // Leaking one half of a split keeps the whole cell exclusive.
+        let (a, b) = RefMut::map_split(x.borrow_mut(), |v| v.split_at_mut(1));
+        std::mem::forget(a);
+        drop(b);
+        assert!(x.try_borrow().is_err());
+        x.get_mut()[0] = 3;
+        x.undo_leak();
+        assert_eq!(*x.borrow(), [3, 2]);
+    }
+
+    #[test]
+    fn traits() {
+        let x: RefCell<Vec<i32>> = RefCell::default();
+        let y = RefCell::from(vec![1]);
+        assert!(x < y);
+        assert_eq!(y.clone(), RefCell::new(vec![1]));
+        assert_eq!(format!("{:?}", y), "RefCell { value: [1] }");
+        let r = y.borrow();
+        assert_eq!(format!("{:?}", y), "RefCell { value: [1] }");
+        assert_eq!(format!("{:?}", r), "[1]");
+        drop(r);
+        let m = y.borrow_mut();
+        assert_eq!(format!("{:?}", y), "RefCell { value: <borrowed> }");
+        assert_eq!(format!("{:?}", m), "[1]");
+        drop(m);
+        let s = RefCell::new(String::from("hi"));
+        assert_eq!(s.borrow().to_string(), "hi");
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn compare_while_exclusive() {
+        let x = RefCell::new(1);
+        let _m = x.borrow_mut();
+        let _ = x == RefCell::new(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn borrow_mut_while_shared() {
+        let x = RefCell::new(());
+        let _a = x.borrow();
+        let _b = x.borrow_mut();
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn borrow_while_exclusive() {
+        let x = RefCell::new(());
+        let _a = x.borrow_mut();
+        let _b = x.borrow();
+    }
+}
+
+#[cfg(all(test, feature = "debug-borrows"))]
+mod debug_borrows_tests {
+    use super::RefCell;
+    use std::panic::Location;
+
+    #[test]
+    fn reports_exclusive_borrow_site() {
+        let x = RefCell::new(());
+        let (_m, at) = (x.borrow_mut(), Location::caller());
+        let e = x.try_borrow().err().unwrap();
+        assert_eq!(e.location().file(), at.file());
+        assert_eq!(e.location().line(), at.line());
+        assert!(e
+            .to_string()
+            .contains(&format!("{}:{}", file!(), at.line())));
+    }
+
+    #[test]
+    fn reports_first_shared_borrow_site() {
+        let x = RefCell::new(());
+        let (_a, at) = (x.borrow(), Location::caller());
+        let _b = x.borrow();
+        let e = x.try_borrow_mut().err().unwrap();
+        assert_eq!(e.location().line(), at.line());
+    }
+
+    #[test]
+    fn location_updates_once_released() {
+        let x = RefCell::new(());
+        drop(x.borrow_mut());
+        let (_a, at) = (x.try_borrow().unwrap(), Location::caller());
+        assert_eq!(
+            x.try_borrow_mut().err().unwrap().location().line(),
+            at.line()
+        );
     }
 }
@@ -0,0 +1,245 @@
+use crate::sync::{self, AtomicUsize, Ordering};
+use std::cell::UnsafeCell;
+
+// The same state machine as RefCell's RefState, packed into one atomic so threads can share it.
+const UNSHARED: usize = 0;
+// Anything in between is Shared(n), the number of live ReadGuard's.
+const EXCLUSIVE: usize = usize::MAX;
+
+// How many times `read`/`write` spin before they start yielding to other threads.
+const SPINS_BEFORE_YIELD: u32 = 100;
+
+// A RefCell that can be shared between threads, a reader-writer lock without the blocking.
+// +Interior Mutability across threads
+// +Many readers at once
+// -Runtime Cost, every borrow is an atomic operation
+// -`read`/`write` spin instead of sleeping, and a steady stream of readers can starve a writer
+pub struct RwCell<T> {
+    value: UnsafeCell<T>,
+    state: AtomicUsize,
+}
+
+// Readers on different threads get &T at the same time, so T must be Sync.
+// A writer on any thread gets &mut T, so T must be Send.
+unsafe impl<T: Send + Sync> Sync for RwCell<T> {}
+
+impl<T> RwCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            state: AtomicUsize::new(UNSHARED),
+        }
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut n = self.state.load(Ordering::Relaxed);
+        loop {
+            if n == EXCLUSIVE {
+                return None;
+            }
+            if n == EXCLUSIVE - 1 {
+                // One more would look like Exclusive.
+                panic!("too many readers");
+            }
+            // Acquire pairs with the Release in WriteGuard's Drop, so we see what the writer wrote.
+            match self
+                .state
+                .compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return Some(ReadGuard { rwcell: self }),
+                Err(old) => n = old,
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        // Acquire pairs with the Release in both guards' Drop, so every earlier reader
+        // and writer is done with the value before we hand out a &mut.
+        self.state
+            .compare_exchange(UNSHARED, EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| WriteGuard { rwcell: self })
+    }
+
+    // Spins until there is no writer.
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut spins = 0;
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            backoff(&mut spins);
+        }
+    }
+
+    // Spins until there are no readers or writers.
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut spins = 0;
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            backoff(&mut spins);
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // &mut self means nobody else can be holding a guard, no need to look at the state.
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+fn backoff(spins: &mut u32) {
+    if *spins < SPINS_BEFORE_YIELD {
+        *spins += 1;
+        sync::spin_loop();
+    } else {
+        // Whoever holds the cell may be waiting for our core, let it run.
+        sync::yield_now();
+    }
+}
+
+pub struct ReadGuard<'rwcell, T> {
+    rwcell: &'rwcell RwCell<T>,
+}
+
+impl<T> std::ops::Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // SAFETY
+        // a ReadGuard is only created if no writer holds the cell.
+        // While it's alive the state is Shared, so no writer can get in.
+        // So dereferencing into a shared reference is fine.
+        unsafe { &*self.rwcell.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release so our reads happen before the next writer's writes.
+        self.rwcell.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+pub struct WriteGuard<'rwcell, T> {
+    rwcell: &'rwcell RwCell<T>,
+}
+
+impl<T> std::ops::Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // SAFETY
+        // a WriteGuard is only created if no other guard holds the cell.
+        // While it's alive the state is Exclusive, so no other guard can get in.
+        // So dereferencing into a shared reference is fine.
+        unsafe { &*self.rwcell.value.get() }
+    }
+}
+
+impl<T> std::ops::DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: same as Deref, and we hold the guard mutably.
+        unsafe { &mut *self.rwcell.value.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Release so our writes happen before the next reader's or writer's accesses.
+        self.rwcell.state.store(UNSHARED, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::RwCell;
+    use crate::arc::Arc;
+
+    #[test]
+    fn exclusive() {
+        let x = RwCell::new(1);
+        let r1 = x.try_read().unwrap();
+        let r2 = x.read();
+        assert_eq!(*r1 + *r2, 2);
+        assert!(x.try_write().is_none());
+        drop(r1);
+        assert!(x.try_write().is_none());
+        drop(r2);
+        let mut w = x.try_write().unwrap();
+        *w += 1;
+        assert!(x.try_read().is_none());
+        assert!(x.try_write().is_none());
+        drop(w);
+        assert_eq!(*x.read(), 2);
+        assert_eq!(x.into_inner(), 2);
+    }
+
+    #[test]
+    fn threads() {
+        let cache = Arc::new(RwCell::new(Vec::new()));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let cache = Arc::clone(&cache);
+                std::thread::spawn(move || {
+                    for j in 0..100 {
+                        if j % 10 == 0 {
+                            cache.write().push(i);
+                        } else {
+                            let v = cache.read();
+                            // Nobody can push while we hold a read guard.
+                            let len = v.len();
+                            std::hint::spin_loop();
+                            assert_eq!(v.len(), len);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let mut cache = Arc::try_unwrap(cache).ok().unwrap();
+        assert_eq!(cache.get_mut().len(), 80);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::RwCell;
+    use crate::arc::Arc;
+    use loom::thread;
+
+    #[test]
+    fn write_excludes_read() {
+        loom::model(|| {
+            let cell = Arc::new(RwCell::new((0, 0)));
+            let writer = Arc::clone(&cell);
+            let t = thread::spawn(move || {
+                if let Some(mut w) = writer.try_write() {
+                    w.0 += 1;
+                    w.1 += 1;
+                }
+            });
+            if let Some(r) = cell.try_read() {
+                assert_eq!(r.0, r.1);
+            }
+            t.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn writers_exclude_each_other() {
+        loom::model(|| {
+            let cell = Arc::new(RwCell::new(0));
+            let other = Arc::clone(&cell);
+            let t = thread::spawn(move || other.try_write().map(|mut w| *w += 1).is_some());
+            let mine = cell.try_write().map(|mut w| *w += 1).is_some();
+            let theirs = t.join().unwrap();
+            assert_eq!(*cell.try_read().unwrap(), mine as i32 + theirs as i32);
+        });
+    }
+}
@@ -0,0 +1,18 @@
+// The atomics Arc and RwCell are built on. Under `--cfg loom` they are swapped for loom's,
+// which run every interleaving of the loom models in the test modules.
+//   RUSTFLAGS="--cfg loom" cargo test -p pointers --release loom
+// The rest of the unsafe code is checked by running the normal tests under Miri.
+//   cargo +nightly miri test -p pointers
+#[cfg(loom)]
+pub(crate) use loom::hint::spin_loop;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(loom)]
+pub(crate) use loom::thread::yield_now;
+
+#[cfg(not(loom))]
+pub(crate) use std::hint::spin_loop;
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{fence, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+pub(crate) use std::thread::yield_now;
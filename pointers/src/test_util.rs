@@ -0,0 +1,68 @@
+use std::cell::Cell;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Set in the child process that `assert_aborts` runs.
+const CHILD_ENV: &str = "POINTERS_EXPECT_ABORT";
+
+// Runs `test` on its own in a child process, and checks that it aborted instead of finishing.
+// Used for the refcount overflow checks, which abort the whole process like std does.
+pub(crate) fn assert_aborts(test: &str) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--ignored", "--test-threads=1"])
+        .env(CHILD_ENV, "1")
+        .output()
+        .unwrap();
+    assert!(
+        !output.status.success(),
+        "{} did not abort:\n{}",
+        test,
+        String::from_utf8_lossy(&output.stdout)
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        assert_eq!(output.status.signal(), Some(6), "{} did not abort", test);
+    }
+}
+
+// Whether this is the child process of `assert_aborts`. The aborting tests are #[ignore]d,
+// and do nothing unless this is set, so `cargo test -- --ignored` doesn't abort.
+pub(crate) fn in_abort_child() -> bool {
+    std::env::var_os(CHILD_ENV).is_some()
+}
+
+// Counts how many times it was dropped, to check that a value is dropped exactly once.
+// The count lives in whatever C is, a Cell here or an atomic for values that cross threads.
+pub(crate) struct DropCounter<C: Count>(pub(crate) C);
+
+impl<C: Count> Drop for DropCounter<C> {
+    fn drop(&mut self) {
+        self.0.add_one();
+    }
+}
+
+pub(crate) trait Count {
+    fn add_one(&self);
+}
+
+impl Count for &Cell<usize> {
+    fn add_one(&self) {
+        self.set(self.get() + 1);
+    }
+}
+
+impl Count for &AtomicUsize {
+    fn add_one(&self) {
+        self.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Loom's atomics only live inside a model, and its threads need 'static, so they're shared
+// through one of loom's Arc's instead of borrowed.
+#[cfg(loom)]
+impl Count for loom::sync::Arc<loom::sync::atomic::AtomicUsize> {
+    fn add_one(&self) {
+        self.fetch_add(1, loom::sync::atomic::Ordering::Relaxed);
+    }
+}